@@ -0,0 +1,269 @@
+use super::matcher::{FindIter, Matcher};
+use std::collections::VecDeque;
+
+/**
+ * An AhoCorasick automaton finds any of a set of strings in a single
+ * pass over the input, however many of them there are. It is a trie of
+ * the strings whose missing transitions are filled in from the failure
+ * link of each state, the state for the longest proper suffix of its
+ * text that is also in the trie, which turns it into a DFA that moves
+ * on one table lookup per byte.
+ *
+ * The strings are matched as raw bytes, so this is what -F uses in place
+ * of an NFA when the strings are taken literally.
+ */
+#[derive(Debug, Clone)]
+pub struct AhoCorasick {
+    /**
+     * The transitions, 256 for each state. The root is state 0.
+     */
+    next: Vec<u32>,
+    /**
+     * The length of the longest string that ends in each state.
+     */
+    longest: Vec<Option<usize>>,
+    /**
+     * The indexes of all of the strings that end in each state.
+     */
+    ends: Vec<Vec<usize>>,
+    /**
+     * The length of the text each state stands for in the trie.
+     */
+    depth: Vec<usize>,
+    lengths: Vec<usize>,
+    max_len: usize,
+}
+
+const ROOT: u32 = 0;
+
+impl AhoCorasick {
+    pub fn new<P: AsRef<[u8]>>(strings: &[P]) -> AhoCorasick {
+        let mut ac = AhoCorasick {
+            next: vec![],
+            longest: vec![],
+            ends: vec![],
+            depth: vec![],
+            lengths: vec![],
+            max_len: 0,
+        };
+        ac.add_state();
+        // build the trie, with None for the transitions it does not have
+        let mut trie: Vec<[Option<u32>; 256]> = vec![[None; 256]];
+        for (i, string) in strings.iter().enumerate() {
+            let string = string.as_ref();
+            let mut state = ROOT;
+            for byte in string {
+                state = match trie[state as usize][*byte as usize] {
+                    Some(next) => next,
+                    None => {
+                        let next = ac.add_state();
+                        trie.push([None; 256]);
+                        ac.depth[next as usize] = ac.depth[state as usize] + 1;
+                        trie[state as usize][*byte as usize] = Some(next);
+                        next
+                    }
+                };
+            }
+            ac.longest[state as usize] = Some(string.len());
+            ac.ends[state as usize].push(i);
+            ac.lengths.push(string.len());
+            ac.max_len = ac.max_len.max(string.len());
+        }
+        // fill in the rest breadth first, so that the failure link of a state, which is
+        // shallower, is finished before it is
+        let mut fail = vec![ROOT; trie.len()];
+        let mut queue = VecDeque::new();
+        for (byte, child) in trie[0].iter().enumerate() {
+            if let Some(child) = *child {
+                ac.next[byte] = child;
+                queue.push_back(child);
+            }
+        }
+        while let Some(state) = queue.pop_front() {
+            let (s, f) = (state as usize, fail[state as usize] as usize);
+            if ac.longest[s].is_none() {
+                ac.longest[s] = ac.longest[f];
+            }
+            let inherited = ac.ends[f].clone();
+            ac.ends[s].extend(inherited);
+            for (byte, child) in trie[s].iter().enumerate() {
+                let on_fail = ac.next[f * 256 + byte];
+                match *child {
+                    Some(child) => {
+                        fail[child as usize] = on_fail;
+                        ac.next[s * 256 + byte] = child;
+                        queue.push_back(child);
+                    }
+                    None => ac.next[s * 256 + byte] = on_fail,
+                }
+            }
+        }
+        for ends in &mut ac.ends {
+            ends.sort_unstable();
+            ends.dedup();
+        }
+        ac
+    }
+
+    fn add_state(&mut self) -> u32 {
+        let id = self.longest.len() as u32;
+        self.next.extend_from_slice(&[ROOT; 256]);
+        self.longest.push(None);
+        self.ends.push(vec![]);
+        self.depth.push(0);
+        id
+    }
+
+    fn step(&self, state: u32, byte: u8) -> u32 {
+        self.next[state as usize * 256 + byte as usize]
+    }
+
+    pub fn find_iter<'a, 'h>(&'a self, haystack: &'h [u8]) -> FindIter<'a, 'h> {
+        FindIter::new(self, haystack)
+    }
+}
+
+impl Matcher for AhoCorasick {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        if self.longest[ROOT as usize].is_some() {
+            return true;
+        }
+        let mut state = ROOT;
+        for byte in haystack {
+            state = self.step(state, *byte);
+            if self.longest[state as usize].is_some() {
+                return true;
+            }
+        }
+        false
+    }
+
+    /**
+     * The longest string ending at each offset is the one that starts
+     * first there. Once the scan is further past the leftmost start found
+     * than the longest string is long, nothing can start before it or
+     * run on past it, so it is the leftmost-longest match.
+     */
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<(usize, usize)> {
+        let mut best = self.longest[ROOT as usize].map(|_| (at, at));
+        let mut state = ROOT;
+        for (i, byte) in haystack.iter().enumerate().skip(at) {
+            if best.is_some_and(|(start, _)| i >= start + self.max_len) {
+                break;
+            }
+            state = self.step(state, *byte);
+            if let Some(len) = self.longest[state as usize] {
+                let (start, end) = (i + 1 - len, i + 1);
+                if best.is_none_or(|(s, e)| start < s || (start == s && end > e)) {
+                    best = Some((start, end));
+                }
+            }
+        }
+        best
+    }
+
+    fn candidate(&self, haystack: &[u8], at: usize) -> Option<usize> {
+        self.find_at(haystack, at).map(|(start, _)| start)
+    }
+
+    fn which(&self, haystack: &[u8]) -> Vec<usize> {
+        let mut matched = self.ends[ROOT as usize].clone();
+        let mut state = ROOT;
+        for byte in haystack {
+            state = self.step(state, *byte);
+            matched.extend(&self.ends[state as usize]);
+        }
+        matched.sort_unstable();
+        matched.dedup();
+        matched
+    }
+
+    /**
+     * Only while the automaton is still following the trie down from the
+     * root is the text since start the text of the state it is in. The
+     * strings that are that whole text are the ones that start at start.
+     */
+    fn matches_at(&self, haystack: &[u8], start: usize) -> Vec<(usize, usize)> {
+        let mut matched = vec![];
+        let mut state = ROOT;
+        let mut pos = start;
+        loop {
+            let depth = self.depth[state as usize];
+            for i in &self.ends[state as usize] {
+                if self.lengths[*i] == depth {
+                    matched.push((pos, *i));
+                }
+            }
+            if pos == haystack.len() {
+                break;
+            }
+            state = self.step(state, haystack[pos]);
+            pos += 1;
+            if self.depth[state as usize] != pos - start {
+                break;
+            }
+        }
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_any() {
+        let ac = AhoCorasick::new(&["he", "she", "his", "hers"]);
+        assert!(ac.is_match(b"ushers"));
+        assert!(!ac.is_match(b"hi t"));
+        assert_eq!(ac.find(b"ushers"), Some((1, 4)));
+        assert_eq!(ac.find_at(b"ushers", 2), Some((2, 6)));
+        assert_eq!(ac.find(b"this"), Some((1, 4)));
+        assert_eq!(ac.find(b"nothing"), None);
+    }
+
+    #[test]
+    fn leftmost_longest() {
+        //cd is found first, but abcdef starts before it
+        let ac = AhoCorasick::new(&["cd", "abcdef", "abc"]);
+        assert_eq!(ac.find(b"xabcdefg"), Some((1, 7)));
+        assert_eq!(ac.find(b"xabcdg"), Some((1, 4)));
+        assert_eq!(ac.candidate(b"xabcdg", 0), Some(1));
+        let matches: Vec<_> = ac.find_iter(b"abc cd abcdef").collect();
+        assert_eq!(matches, vec![(0, 3), (4, 6), (7, 13)]);
+    }
+
+    #[test]
+    fn which() {
+        let ac = AhoCorasick::new(&["error", "disk", "rror", "error"]);
+        assert_eq!(ac.which(b"disk error"), vec![0, 1, 2, 3]);
+        assert_eq!(ac.which(b"rror"), vec![2]);
+        assert_eq!(ac.which(b"ok"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn matches_at() {
+        let ac = AhoCorasick::new(&["foo", "foobar", "oba", "o"]);
+        assert_eq!(ac.matches_at(b"xfoobar", 1), vec![(4, 0), (7, 1)]);
+        assert_eq!(ac.matches_at(b"xfoobar", 2), vec![(3, 3)]);
+        assert_eq!(ac.matches_at(b"xfoobar", 0), vec![]);
+        assert!(ac.accepts(b"foobar"));
+        assert!(!ac.accepts(b"fooba"));
+    }
+
+    #[test]
+    fn empty_string() {
+        let ac = AhoCorasick::new(&["", "b"]);
+        assert!(ac.is_match(b""));
+        assert_eq!(ac.find(b"ab"), Some((0, 0)));
+        assert_eq!(ac.find_at(b"ab", 1), Some((1, 2)));
+        assert!(!AhoCorasick::new::<&str>(&[]).is_match(b"anything"));
+    }
+
+    #[test]
+    fn raw_bytes() {
+        let ac = AhoCorasick::new(&["café", "\u{ff}"]);
+        assert_eq!(ac.find("un café".as_bytes()), Some((3, 8)));
+        assert!(!ac.is_match(&[0xff]));
+    }
+}
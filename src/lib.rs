@@ -0,0 +1,19 @@
+/**
+ * thegrep - Tar Heel egrep
+ *
+ * The modules thegrep is built from, for use from code as well as from
+ * the command line. A Regex (regex.rs) is the simplest way in: it
+ * compiles a pattern and finds or replaces its matches in a str.
+ */
+pub mod aho_corasick;
+pub mod matcher;
+pub mod nfa;
+pub mod parser;
+pub mod pool;
+pub mod printer;
+pub mod regex;
+pub mod search;
+pub mod tokenizer;
+pub mod walk;
+
+pub use self::regex::Regex;
@@ -17,14 +17,14 @@ use structopt::StructOpt;
 //importing library fot std in/out
 use std::io;
 
-pub mod nfa;
-use self::nfa::helpers::nfa_dot;
-use self::nfa::NFA;
+use thegrep::nfa::helpers::nfa_dot;
+use thegrep::nfa::NFA;
+use thegrep::aho_corasick::AhoCorasick;
+use thegrep::matcher::{Boundary, Bounded, Matcher};
 
 //set up structopt derivation for flags of thegrep
 #[derive(Debug, StructOpt)]
 #[structopt(name = "thegrep", about = "Tar Heel egrep", author = "")]
-
 //declring optionals for thegrep function
 struct Opt {
     #[structopt(short = "p", long = "parse", help = "Show Parsed AST")]
@@ -35,120 +35,576 @@ struct Opt {
 
     #[structopt(short = "g", long = "gen", default_value = "0")]
     reps: u64,
+
+    #[structopt(
+        short = "F",
+        long = "fixed-strings",
+        raw(conflicts_with_all = r#"&["parse", "tokens", "reps", "dot"]"#),
+        help = "Take the patterns as literal strings rather than regular expressions"
+    )]
+    fixed_strings: bool,
     
-    #[structopt(help = "Regular Expression Pattern")]
-    pattern: String,
+    #[structopt(help = "Regular Expression Pattern, unless given with -e or -f")]
+    pattern: Option<String>,
+
+    #[structopt(
+        short = "e",
+        long = "regexp",
+        raw(number_of_values = "1"),
+        help = "Search for PATTERN, which may be given more than once"
+    )]
+    regexp: Vec<String>,
+
+    #[structopt(
+        short = "f",
+        long = "file",
+        raw(number_of_values = "1"),
+        help = "Search for each pattern in FILE, one per line"
+    )]
+    pattern_file: Vec<String>,
+
+    #[structopt(
+        long = "which",
+        help = "Prefix lines with the numbers of the patterns that match them, -e before -f"
+    )]
+    which: bool,
 
     #[structopt(short = "d", long = "dot")]
     dot: bool,
 
-    #[structopt(help = "FILES")]
+    #[structopt(short = "i", long = "ignore-case", help = "Ignore case distinctions in the pattern")]
+    ignore_case: bool,
+
+    #[structopt(short = "v", long = "invert-match", help = "Select lines that do not match")]
+    invert: bool,
+
+    #[structopt(short = "w", long = "word-regexp", help = "Only match whole words")]
+    word_regexp: bool,
+
+    #[structopt(
+        short = "x",
+        long = "line-regexp",
+        help = "Only match whole lines, taking precedence over -w"
+    )]
+    line_regexp: bool,
+
+    #[structopt(short = "c", long = "count", help = "Print only a count of selected lines per file")]
+    count: bool,
+
+    #[structopt(short = "q", long = "quiet", help = "Print nothing, exit on the first match")]
+    quiet: bool,
+
+    #[structopt(short = "m", long = "max-count", help = "Stop after NUM selected lines")]
+    max_count: Option<u64>,
+
+    #[structopt(short = "l", long = "files-with-matches", help = "Print only names of files with selected lines")]
+    files_with_matches: bool,
+
+    #[structopt(short = "L", long = "files-without-match", help = "Print only names of files without selected lines")]
+    files_without_match: bool,
+
+    #[structopt(short = "A", long = "after-context", help = "Print NUM lines of context after each selected line")]
+    after_context: Option<usize>,
+
+    #[structopt(short = "B", long = "before-context", help = "Print NUM lines of context before each selected line")]
+    before_context: Option<usize>,
+
+    #[structopt(short = "C", long = "context", help = "Print NUM lines of context around each selected line")]
+    context: Option<usize>,
+
+    #[structopt(short = "n", long = "line-number", help = "Prefix each line with its line number")]
+    line_number: bool,
+
+    #[structopt(short = "b", long = "byte-offset", help = "Prefix each line with its byte offset")]
+    byte_offset: bool,
+
+    #[structopt(long = "column", help = "Prefix each line with the column of its first match")]
+    column: bool,
+
+    #[structopt(short = "H", long = "with-filename", help = "Prefix each line with its file name")]
+    with_filename: bool,
+
+    #[structopt(short = "h", long = "no-filename", help = "Never prefix lines with file names")]
+    no_filename: bool,
+
+    #[structopt(long = "vimgrep", help = "Print lines as file:line:column:text for editors")]
+    vimgrep: bool,
+
+    #[structopt(short = "o", long = "only-matching", help = "Print only the matched parts of lines")]
+    only_matching: bool,
+
+    #[structopt(
+        long = "replace",
+        help = "Print matches replaced with TEMPLATE, where $1 or ${name} is what a group matched"
+    )]
+    replace: Option<String>,
+
+    #[structopt(
+        long = "color",
+        default_value = "auto",
+        raw(possible_values = r#"&["auto", "always", "never"]"#),
+        help = "When to highlight matches, file names and line numbers"
+    )]
+    color: ColorChoice,
+
+    #[structopt(short = "r", long = "recursive", help = "Search directories recursively")]
+    recursive: bool,
+
+    #[structopt(
+        short = "R",
+        long = "dereference-recursive",
+        help = "Search directories recursively, following symbolic links"
+    )]
+    dereference_recursive: bool,
+
+    #[structopt(
+        long = "include",
+        raw(number_of_values = "1"),
+        help = "Search only files whose names match GLOB"
+    )]
+    include: Vec<String>,
+
+    #[structopt(
+        long = "exclude",
+        raw(number_of_values = "1"),
+        help = "Skip files whose names match GLOB"
+    )]
+    exclude: Vec<String>,
+
+    #[structopt(long = "hidden", help = "Search hidden files and directories")]
+    hidden: bool,
+
+    #[structopt(long = "no-ignore", help = "Don't skip files listed in .gitignore and .ignore")]
+    no_ignore: bool,
+
+    #[structopt(short = "j", long = "threads", help = "Search NUM files at once [default: one per CPU]")]
+    threads: Option<usize>,
+
+    #[structopt(short = "a", long = "text", help = "Search binary files as if they were text")]
+    text: bool,
+
+    #[structopt(
+        long = "binary-files",
+        default_value = "binary",
+        raw(possible_values = r#"&["binary", "text", "without-match"]"#),
+        help = "How to treat files that contain NUL bytes"
+    )]
+    binary_files: BinaryFiles,
+
+    #[structopt(help = "FILES, or - for standard input")]
     path: Vec<String>,
 
 }
 
 //importing tokenizer and parser functionalities from the other files
-pub mod tokenizer;
-use self::tokenizer::Tokenizer;
-pub mod parser;
-use self::parser::Parser;
-use std::fs::File;
-use std::io::BufRead;
+use thegrep::pool;
+use thegrep::tokenizer::Tokenizer;
+use thegrep::parser::{Flags, Parser};
+use thegrep::printer::{ColorChoice, Output, PrintOptions, Printer};
+use thegrep::regex::Template;
+use thegrep::search::{BinaryFiles, SearchOptions, Searcher};
+use thegrep::walk::{Entry, Glob, WalkOptions, Walker};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::io::{IsTerminal, Write};
+use std::process::exit;
+
+//exit statuses follow POSIX grep: a line was selected, no lines were selected, or trouble
+const STATUS_MATCH: i32 = 0;
+const STATUS_NO_MATCH: i32 = 1;
+const STATUS_ERROR: i32 = 2;
 
 //main takes in opt from the args passed in on the command line, if it encounters the parse or
 //tokens flag, it will carry out the helped functions for each respectively
 fn main() {
-    let opt = Opt::from_args();
+    let opt = match Opt::from_iter_safe(std::env::args()) {
+        Ok(opt) => opt,
+        Err(e) => {
+            //--help and --version are not errors and exit successfully
+            if !e.use_stderr() {
+                e.exit();
+            }
+            eprintln!("{}", e.message);
+            exit(STATUS_ERROR);
+        }
+    };
 
+    let (patterns, paths) = patterns_and_paths(&opt);
     if opt.tokens {
-        eval_show_tokens(&opt.pattern);
+        eval_show_tokens(&patterns);
     }
     if opt.parse {
-        eval_show_parse(&opt.pattern);
+        eval_show_parse(&patterns, flags(&opt));
     }
-    if opt.dot {
-        eval_show_dot(&opt.pattern);
+    let matcher: Box<dyn Matcher> = if opt.fixed_strings {
+        fixed(&patterns, flags(&opt))
+    } else {
+        let nfa = compile(&patterns, flags(&opt));
+        if opt.dot {
+            eval_show_dot(&nfa);
+        }
+        if (opt.reps > 0)  {
+            eval_show_gen(&nfa, opt.reps);
+        }
+        nfa.into_matcher()
+    };
+    let matcher = bounded(&opt, matcher);
+
+    let entries = entries(&opt, &paths);
+    let searcher = Searcher::new(&*matcher, search_options(&opt));
+    let stdout = io::stdout();
+    let print_options = print_options(&opt, paths.len());
+    let mut printer = Printer::new(stdout.lock(), &*matcher, print_options.clone());
+    let threads = opt.threads.unwrap_or_else(|| {
+        std::thread::available_parallelism().map_or(1, |threads| threads.get())
+    });
+    let status = if threads > 1 && entries.len() > 1 {
+        read_files_parallel(&opt, &entries, threads, &searcher, &mut printer, &print_options)
+    } else {
+        read_files(&opt, entries, &searcher, &mut printer)
+    };
+    //a line the backtracking matcher gave up on may have had a match in it, which is an error
+    //like an unreadable file is
+    if matcher.gave_up() {
+        eprintln!("thegrep: pattern exceeded the backtracking step limit on some lines, which may have had matches");
+        if !(opt.quiet && status == STATUS_MATCH) {
+            exit(STATUS_ERROR);
+        }
     }
-    if (opt.reps > 0)  {
-        let nfa = NFA::from(&opt.pattern).unwrap();
-        eval_show_gen(&nfa, opt.reps);
+    exit(status);
+}
+
+//the flags set for the whole pattern from the command line
+fn flags(opt: &Opt) -> Flags {
+    Flags {
+        case_insensitive: opt.ignore_case,
+        ..Flags::default()
     }
+}
 
-    let input = &opt.pattern;
-    let nfa = NFA::from(&format!(".*({}).*", input)).unwrap();
-    if opt.path.len() > 0 {
-        let matches = read_files(&opt, &nfa);
-        match matches {
-            Ok(()) => return,
-            Err(e) => eprintln!("{}", e),
+//builds a single NFA for all of the patterns, or points out what is wrong with one and exits
+fn compile(patterns: &[String], flags: Flags) -> NFA {
+    match NFA::with_patterns(patterns, flags) {
+        Ok(nfa) => nfa,
+        Err((i, e)) => {
+            eprintln!("thegrep: {}", e.diagnostic(&patterns[i]));
+            exit(STATUS_ERROR);
         }
+    }
+}
+
+//builds the matcher for -F, which skips the tokenizer and parser. Aho-Corasick matches bytes
+//exactly, so ignoring case falls back to an NFA of the literal strings, which folds case the same
+//way a regular expression does
+fn fixed(patterns: &[String], flags: Flags) -> Box<dyn Matcher> {
+    if flags.case_insensitive {
+        Box::new(NFA::with_literals(patterns, flags))
     } else {
-        print_stdin(&nfa);
+        Box::new(AhoCorasick::new(patterns))
     }
 }
 
-fn print_stdin(nfa: &NFA) {
-    let stdin = io::stdin();
-    let reader = stdin.lock();
-    check(nfa, reader);
+//restricts matches to whole lines for -x or whole words for -w
+fn bounded(opt: &Opt, matcher: Box<dyn Matcher>) -> Box<dyn Matcher> {
+    if opt.line_regexp {
+        Box::new(Bounded::new(matcher, Boundary::Line))
+    } else if opt.word_regexp {
+        Box::new(Bounded::new(matcher, Boundary::Word))
+    } else {
+        matcher
+    }
 }
 
-fn read_files(opt: &Opt, nfa: &NFA) -> io::Result<()> {
-    for paths in opt.path.iter() {
-        let file = File::open(paths)?;
-        let reader = io::BufReader::new(file);
-        check(nfa, reader);
+//the patterns to search for and the paths to search. Like grep, when patterns are given with -e
+//or -f, the first positional argument is a path rather than a pattern. Patterns from -e come
+//first, then those read from each -f file in turn, one per line
+fn patterns_and_paths(opt: &Opt) -> (Vec<String>, Vec<String>) {
+    let mut paths = opt.path.clone();
+    if opt.regexp.is_empty() && opt.pattern_file.is_empty() {
+        return match &opt.pattern {
+            Some(pattern) => (vec![pattern.clone()], default_paths(opt, paths)),
+            None => {
+                eprintln!("thegrep: no pattern given\n\nFor more information try --help");
+                exit(STATUS_ERROR);
+            }
+        };
+    }
+    if let Some(path) = &opt.pattern {
+        paths.insert(0, path.clone());
+    }
+    let mut patterns = opt.regexp.clone();
+    for file in &opt.pattern_file {
+        match std::fs::read(file) {
+            Ok(bytes) => patterns.extend(String::from_utf8_lossy(&bytes).lines().map(String::from)),
+            Err(e) => {
+                eprintln!("thegrep: {}: {}", file, e);
+                exit(STATUS_ERROR);
+            }
+        }
+    }
+    (patterns, default_paths(opt, paths))
+}
+
+//options for which lines the search selects and which lines around them it passes on as
+//context. -A and -B take precedence over -C, and context only matters when whole lines are printed
+fn search_options(opt: &Opt) -> SearchOptions {
+    let context = |n: Option<usize>| match output(opt) {
+        Output::Lines if !opt.only_matching => n.or(opt.context).unwrap_or(0),
+        _ => 0,
+    };
+    SearchOptions {
+        invert: opt.invert,
+        max_count: opt.max_count,
+        binary: if opt.text {
+            BinaryFiles::Text
+        } else {
+            opt.binary_files
+        },
+        before_context: context(opt.before_context),
+        after_context: context(opt.after_context),
+    }
+}
+
+//the paths to search. When none are given, standard input is searched, or the current directory
+//when searching recursively
+fn default_paths(opt: &Opt, paths: Vec<String>) -> Vec<String> {
+    if !paths.is_empty() {
+        paths
+    } else if recursive(opt) {
+        vec![String::from(".")]
+    } else {
+        vec![String::from("-")]
+    }
+}
+
+fn recursive(opt: &Opt) -> bool {
+    opt.recursive || opt.dereference_recursive
+}
+
+//the files to search at each path, walking directories when searching recursively
+fn entries(opt: &Opt, paths: &[String]) -> Vec<Entry> {
+    if !recursive(opt) {
+        return paths.iter().map(|path| Entry::File(PathBuf::from(path))).collect();
+    }
+    let walker = Walker::new(WalkOptions {
+        include: opt.include.iter().map(|glob| Glob::new(glob)).collect(),
+        exclude: opt.exclude.iter().map(|glob| Glob::new(glob)).collect(),
+        hidden: opt.hidden,
+        ignore_files: !opt.no_ignore,
+        follow_links: opt.dereference_recursive,
+    });
+    let mut entries = vec![];
+    for path in paths {
+        if path == "-" {
+            entries.push(Entry::File(PathBuf::from(path)));
+        } else {
+            entries.extend(walker.walk(Path::new(path)));
+        }
+    }
+    entries
+}
+
+//what gets printed for each file and line. Like grep, file names are only printed by default
+//when there may be more than one file to tell apart, and --vimgrep turns on everything an editor
+//needs to jump to a match. Color is only on by default when a person will see it in a terminal
+fn print_options(opt: &Opt, files: usize) -> PrintOptions {
+    PrintOptions {
+        output: output(opt),
+        with_filename: !opt.no_filename
+            && (opt.with_filename || opt.vimgrep || recursive(opt) || files > 1),
+        line_number: opt.line_number || opt.vimgrep,
+        column: opt.column || opt.vimgrep,
+        byte_offset: opt.byte_offset,
+        which: opt.which,
+        only_matching: opt.only_matching,
+        replace: opt.replace.as_deref().map(Template::new),
+        color: match opt.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => io::stdout().is_terminal(),
+        },
+    }
+}
+
+//what gets printed about each file searched
+fn output(opt: &Opt) -> Output {
+    if opt.quiet {
+        Output::Quiet
+    } else if opt.files_with_matches {
+        Output::FilesWithMatches
+    } else if opt.files_without_match {
+        Output::FilesWithoutMatch
+    } else if opt.count {
+        Output::Count
+    } else {
+        Output::Lines
+    }
+}
+
+//searches every file in turn. A path that cannot be walked, opened or read is reported and
+//skipped so that the remaining files are still searched, but the exit status records the trouble.
+//With -q, the first match ends everything successfully
+fn read_files<W: Write>(
+    opt: &Opt,
+    entries: Vec<Entry>,
+    searcher: &Searcher,
+    printer: &mut Printer<W>,
+) -> i32 {
+    let mut matched = false;
+    let mut failed = false;
+    for entry in entries {
+        let result = match entry {
+            Entry::File(path) => check(searcher, &path, printer).map_err(|e| (path, e)),
+            Entry::Error(path, e) => Err((path, e)),
+        };
+        match result {
+            Ok(matches) => matched |= matches > 0,
+            Err((path, e)) => {
+                report(&path, &e);
+                failed = true;
+            }
+        }
+        if matched && opt.quiet {
+            return STATUS_MATCH;
+        }
+    }
+    if failed {
+        STATUS_ERROR
+    } else if matched {
+        STATUS_MATCH
+    } else {
+        STATUS_NO_MATCH
     }
-    Ok(())
 }
 
-fn check<R: BufRead>(nfa: &NFA, reader: R) {
-    for line in reader.lines() {
-        if let Ok(point) = line {
-            if nfa.accepts(&point) {
-                println!("{}", &point);
+//searches files like read_files, but on a pool of threads. Each file's output is buffered by a
+//printer of its own and appended to the real one in order, so that output is the same as it would
+//be searching one file at a time
+fn read_files_parallel<'m, W: Write>(
+    opt: &Opt,
+    entries: &[Entry],
+    threads: usize,
+    searcher: &Searcher<'m>,
+    printer: &mut Printer<'m, W>,
+    print_options: &PrintOptions,
+) -> i32 {
+    let mut matched = false;
+    let mut failed = false;
+    let search = |entry: &Entry| match entry {
+        Entry::File(path) => {
+            let mut buffered = Printer::new(Vec::new(), searcher.matcher(), print_options.clone());
+            let result = check(searcher, path, &mut buffered);
+            Some((buffered, result))
+        }
+        Entry::Error(..) => None,
+    };
+    pool::ordered(entries, threads, search, |entry, searched| {
+        match (entry, searched) {
+            (Entry::File(path), Some((buffered, result))) => {
+                match printer.append(buffered).and(result) {
+                    Ok(matches) => matched |= matches > 0,
+                    Err(e) => {
+                        report(path, &e);
+                        failed = true;
+                    }
+                }
+            }
+            (Entry::Error(path, e), _) => {
+                report(path, e);
+                failed = true;
             }
+            (Entry::File(_), None) => unreachable!("files are always searched"),
         }
+        !(matched && opt.quiet)
+    });
+    if matched && opt.quiet {
+        STATUS_MATCH
+    } else if failed {
+        STATUS_ERROR
+    } else if matched {
+        STATUS_MATCH
+    } else {
+        STATUS_NO_MATCH
+    }
+}
+
+//prints an error about a path to stderr. When stdout has gone away, as with `thegrep x | head`,
+//there is nobody left to print results to so we stop quietly instead
+fn report(path: &Path, e: &io::Error) {
+    if e.kind() == io::ErrorKind::BrokenPipe {
+        exit(STATUS_ERROR);
+    }
+    eprintln!("thegrep: {}: {}", name(path), e);
+}
+
+//standard input is searched for the path -, and goes by the name grep gives it
+fn name(path: &Path) -> Cow<'_, str> {
+    if path == Path::new("-") {
+        Cow::from("(standard input)")
+    } else {
+        path.to_string_lossy()
+    }
+}
+
+//searches the file at path with the searcher, sending what it selects to the printer, and
+//returns the number of lines selected
+fn check<W: Write>(searcher: &Searcher, path: &Path, printer: &mut Printer<W>) -> io::Result<u64> {
+    let name = name(path);
+    let mut sink = printer.sink(&name);
+    if path == Path::new("-") {
+        let stdin = io::stdin();
+        let reader = stdin.lock();
+        searcher.search(reader, &mut sink)
+    } else {
+        searcher.search_path(path, &mut sink)
     }
 }
 
 //declares a mutable tokenizer for input. Then loops through this input and tokenizes the
 //individual elements of input and prtins them to stdout
-fn eval_show_tokens(input: &str) {
-    let mut tokens = Tokenizer::new(input);
-    while let Some(token) = tokens.next() {
-        println!("{:?}", token);
+fn eval_show_tokens(patterns: &[String]) {
+    for input in patterns {
+        for token in Tokenizer::new(input) {
+            println!("{:?}", token);
+        }
+        println!();
     }
-    print!("\n");
-    std::process::exit(0);
+    exit(STATUS_MATCH);
 }
 
 //declares a parser to parse a tokenizer of input. If everything is parsed, the returnes statement
 //from parser.rs is printed, otherwise an error is printed to stderr
-fn eval_show_parse(input: &str) {
-    match Parser::parse(Tokenizer::new(input)) {
-        Ok(statement) => {
-            println!("{:?}", statement);
+fn eval_show_parse(patterns: &[String], flags: Flags) {
+    for input in patterns {
+        match Parser::parse_with_flags(Tokenizer::new(input), flags) {
+            Ok(statement) => {
+                println!("{:?}", statement);
+            }
+            Err(e) => {
+                eprintln!("thegrep: {}", e.diagnostic(input));
+                exit(STATUS_ERROR);
+            }
         }
-        Err(msg) => eprintln!("thegrep: {}", msg),
+        println!("\n");
     }
-    println!("\n");
-    std::process::exit(0);
+    exit(STATUS_MATCH);
 }
 
 //helper method for when dot flag is used
-fn eval_show_dot(input: &str) {
-    let nfa = NFA::from(&input).unwrap();
-    println!("{}", nfa_dot(&nfa));
-    std::process::exit(0);
+fn eval_show_dot(nfa: &NFA) {
+    println!("{}", nfa_dot(nfa));
+    exit(STATUS_MATCH);
 }
 
+//prints num generated strings. This loops rather than recursing so that large counts cannot
+//overflow the stack
 fn eval_show_gen(nfa: &NFA, num: u64) {
-    if (num > 0) {
-        println!("{}", nfa.gen());
-        eval_show_gen(nfa, num - 1);
+    if nfa.has_lookaround() {
+        eprintln!("thegrep: -g cannot generate strings for patterns with lookaround");
+        exit(STATUS_ERROR);
     }
-    if (num == 0) {
-        std::process::exit(0);
+    for _ in 0..num {
+        println!("{}", nfa.gen());
     }
+    exit(STATUS_MATCH);
 }
@@ -0,0 +1,339 @@
+/**
+ * A Matcher finds where lines match, which is all the Searcher and
+ * Printer need to know about it. Regular expressions are matched by an
+ * NFA, or by a Backtrack matcher when they have backreferences, and
+ * fixed strings by an AhoCorasick automaton.
+ *
+ * Offsets are in bytes and matches are leftmost-longest, unless lazy
+ * quantifiers make them leftmost-first (see MatchKind).
+ */
+pub trait Matcher: Sync {
+    /**
+     * Whether there is a match anywhere within haystack.
+     */
+    fn is_match(&self, haystack: &[u8]) -> bool;
+
+    /**
+     * The start and end of the leftmost match in haystack that starts at
+     * or after the offset at.
+     */
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<(usize, usize)>;
+
+    /**
+     * The offset of the next place at or after at where a match could
+     * be. A line with a match in it has a candidate in it too, so lines
+     * between candidates can be skipped. Finding it has to be cheap and
+     * must not run the matcher over several lines at once, so a matcher
+     * that cannot skip anything gives at itself.
+     */
+    fn candidate(&self, haystack: &[u8], at: usize) -> Option<usize>;
+
+    /**
+     * The indexes of the patterns that match anywhere within haystack,
+     * in order.
+     */
+    fn which(&self, haystack: &[u8]) -> Vec<usize>;
+
+    /**
+     * The ends of all of the matches that start at the offset start, in
+     * order, each with the index of the pattern it is a match of. This is
+     * every match, not just the longest, for -w to pick from.
+     */
+    fn matches_at(&self, haystack: &[u8], start: usize) -> Vec<(usize, usize)>;
+
+    /**
+     * Whether the whole of haystack is a match.
+     */
+    fn accepts(&self, haystack: &[u8]) -> bool {
+        self.matches_at(haystack, 0).iter().any(|(end, _)| *end == haystack.len())
+    }
+
+    fn find(&self, haystack: &[u8]) -> Option<(usize, usize)> {
+        self.find_at(haystack, 0)
+    }
+
+    /**
+     * Where each group matched in the match from start to end, which is
+     * one find_at found, with the offsets group n starts and ends at in
+     * slots 2n and 2n+1 and the whole match as group 0. A group that took
+     * no part in the match has None in its slots. Only matchers of
+     * regular expressions have groups past 0.
+     */
+    fn captures(&self, _haystack: &[u8], start: usize, end: usize) -> Vec<Option<usize>> {
+        vec![Some(start), Some(end)]
+    }
+
+    /**
+     * The number of the group called name, if there is one.
+     */
+    fn group_index(&self, _name: &str) -> Option<usize> {
+        None
+    }
+
+    /**
+     * Whether a search ever gave up on a haystack before it was done, in
+     * which case a match in it may have been missed. Only a Backtrack
+     * matcher ever gives up.
+     */
+    fn gave_up(&self) -> bool {
+        false
+    }
+}
+
+/**
+ * Which of the matches that start at the leftmost place with any is the
+ * match that is found there.
+ * - LeftmostLongest takes the longest of them, as POSIX does
+ * - LeftmostFirst takes the one a backtracking search tries first, which
+ *   prefers the left side of an alternation, and for a quantifier as many
+ *   repeats as it can when it is greedy and as few as it can when it is lazy
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MatchKind {
+    #[default]
+    LeftmostLongest,
+    LeftmostFirst,
+}
+
+/**
+ * What a match has to line up with to count.
+ * - Word matches have no word character right before or after them
+ * - Line matches take up the whole line
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Boundary {
+    Word,
+    Line,
+}
+
+/**
+ * A Bounded matcher only counts the matches of another that line up with
+ * a Boundary, which is how -w and -x are matched whatever the patterns
+ * are. A word match is the longest match at the leftmost start that has
+ * one, so "foo" finds the second foo in "foobar foo", and one pattern
+ * failing at a start does not stop a shorter match there from counting.
+ */
+pub struct Bounded {
+    matcher: Box<dyn Matcher>,
+    boundary: Boundary,
+}
+
+impl Bounded {
+    pub fn new(matcher: Box<dyn Matcher>, boundary: Boundary) -> Bounded {
+        Bounded { matcher, boundary }
+    }
+
+    /**
+     * The matches that start at start and line up with the boundary.
+     */
+    fn bounded_at(&self, haystack: &[u8], start: usize) -> Vec<(usize, usize)> {
+        let mut matches = self.matcher.matches_at(haystack, start);
+        match self.boundary {
+            Boundary::Line => matches.retain(|(end, _)| start == 0 && *end == haystack.len()),
+            Boundary::Word if !word_before(haystack, start) => {
+                matches.retain(|(end, _)| !word_after(haystack, *end))
+            }
+            Boundary::Word => matches.clear(),
+        }
+        matches
+    }
+
+    /**
+     * The starts of the matches of the inner matcher at or after at, in
+     * order, which are the only places a bounded match can start.
+     */
+    fn starts<'h>(&'h self, haystack: &'h [u8], at: usize) -> impl Iterator<Item = usize> + 'h {
+        let mut at = at;
+        std::iter::from_fn(move || {
+            if at > haystack.len() {
+                return None;
+            }
+            let (start, _) = self.matcher.find_at(haystack, at)?;
+            at = start + 1;
+            Some(start)
+        })
+    }
+}
+
+impl Matcher for Bounded {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        match self.boundary {
+            Boundary::Line => self.matcher.accepts(haystack),
+            Boundary::Word => self.find(haystack).is_some(),
+        }
+    }
+
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<(usize, usize)> {
+        if self.boundary == Boundary::Line {
+            return (at == 0 && self.matcher.accepts(haystack)).then_some((0, haystack.len()));
+        }
+        self.starts(haystack, at).find_map(|start| {
+            let ends = self.bounded_at(haystack, start);
+            ends.iter().map(|(end, _)| *end).max().map(|end| (start, end))
+        })
+    }
+
+    fn candidate(&self, haystack: &[u8], at: usize) -> Option<usize> {
+        self.matcher.candidate(haystack, at)
+    }
+
+    fn which(&self, haystack: &[u8]) -> Vec<usize> {
+        let starts: Vec<_> = match self.boundary {
+            Boundary::Line => vec![0],
+            Boundary::Word => self.starts(haystack, 0).collect(),
+        };
+        let mut which: Vec<_> = starts
+            .into_iter()
+            .flat_map(|start| self.bounded_at(haystack, start))
+            .map(|(_, i)| i)
+            .collect();
+        which.sort_unstable();
+        which.dedup();
+        which
+    }
+
+    fn matches_at(&self, haystack: &[u8], start: usize) -> Vec<(usize, usize)> {
+        self.bounded_at(haystack, start)
+    }
+
+    fn captures(&self, haystack: &[u8], start: usize, end: usize) -> Vec<Option<usize>> {
+        self.matcher.captures(haystack, start, end)
+    }
+
+    fn group_index(&self, name: &str) -> Option<usize> {
+        self.matcher.group_index(name)
+    }
+
+    fn gave_up(&self) -> bool {
+        self.matcher.gave_up()
+    }
+}
+
+/**
+ * Word characters are letters and digits in any script, and '_'.
+ */
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/**
+ * Whether the char that ends right at the offset at is a word character.
+ * Bytes that are not valid UTF-8 are not word characters.
+ */
+pub fn word_before(haystack: &[u8], at: usize) -> bool {
+    (1..=4.min(at))
+        .find_map(|len| decode(&haystack[at - len..at]))
+        .is_some_and(is_word_char)
+}
+
+/**
+ * Whether the char that starts at the offset at is a word character.
+ */
+pub fn word_after(haystack: &[u8], at: usize) -> bool {
+    (1..=4.min(haystack.len() - at))
+        .find_map(|len| decode(&haystack[at..at + len]))
+        .is_some_and(is_word_char)
+}
+
+/**
+ * The first char of bytes, as long as all of bytes is valid UTF-8.
+ */
+pub fn decode(bytes: &[u8]) -> Option<char> {
+    std::str::from_utf8(bytes).ok()?.chars().next()
+}
+
+/**
+ * An iterator over the matches of a Matcher in a haystack. An empty match
+ * right where the previous match ended is skipped, so that "a*" finds
+ * "aa" in "aab" once rather than following it with an empty match.
+ */
+pub struct FindIter<'m, 'h> {
+    matcher: &'m dyn Matcher,
+    haystack: &'h [u8],
+    at: usize,
+    last_end: Option<usize>,
+}
+
+impl<'m, 'h> FindIter<'m, 'h> {
+    pub fn new(matcher: &'m dyn Matcher, haystack: &'h [u8]) -> FindIter<'m, 'h> {
+        FindIter {
+            matcher,
+            haystack,
+            at: 0,
+            last_end: None,
+        }
+    }
+}
+
+impl<'m, 'h> Iterator for FindIter<'m, 'h> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        loop {
+            if self.at > self.haystack.len() {
+                return None;
+            }
+            let (start, end) = self.matcher.find_at(self.haystack, self.at)?;
+            if start == end {
+                //step past an empty match so the next search makes progress
+                self.at = end + 1;
+                if Some(end) == self.last_end {
+                    continue;
+                }
+            } else {
+                self.at = end;
+            }
+            self.last_end = Some(end);
+            return Some((start, end));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aho_corasick::AhoCorasick;
+    use crate::nfa::NFA;
+
+    fn word(pattern: &str) -> Bounded {
+        Bounded::new(Box::new(NFA::from(pattern).unwrap()), Boundary::Word)
+    }
+
+    #[test]
+    fn words() {
+        let foo = word("foo");
+        assert!(foo.is_match(b"a foo."));
+        assert!(foo.is_match(b"foo"));
+        assert!(!foo.is_match(b"foobar"));
+        assert!(!foo.is_match(b"_foo"));
+        assert_eq!(foo.find(b"foobar foo"), Some((7, 10)));
+        assert!(!foo.is_match("éfoo".as_bytes()));
+        assert!(foo.is_match("—foo—".as_bytes()));
+        assert!(!word("ab*").is_match(b"abbbc"));
+    }
+
+    #[test]
+    fn shorter_words() {
+        //the longest match at a start may not be a word when a shorter one is
+        let shorter = word("ab|abc");
+        assert_eq!(shorter.find(b"abc"), Some((0, 3)));
+        assert_eq!(shorter.find(b"abcd ab"), Some((5, 7)));
+        assert_eq!(word("a+").find(b"aab"), None);
+        let ac = Bounded::new(Box::new(AhoCorasick::new(&["ab", "abc"])), Boundary::Word);
+        assert_eq!(ac.find(b"ab-abcx"), Some((0, 2)));
+        assert_eq!(ac.which(b"ab-abcx"), vec![0]);
+    }
+
+    #[test]
+    fn lines() {
+        let line = Bounded::new(Box::new(NFA::from("a(b|c)*").unwrap()), Boundary::Line);
+        assert!(line.is_match(b"abcb"));
+        assert!(!line.is_match(b"abcd"));
+        assert!(!line.is_match(b"xab"));
+        assert_eq!(line.find(b"abc"), Some((0, 3)));
+        let ac = Bounded::new(Box::new(AhoCorasick::new(&["id-1", "id-10"])), Boundary::Line);
+        assert!(ac.is_match(b"id-10"));
+        assert!(!ac.is_match(b"id-100"));
+        assert_eq!(ac.which(b"id-10"), vec![1]);
+    }
+}
@@ -1,7 +1,14 @@
+mod backtrack;
+mod charset;
 pub mod helpers;
+mod literal;
+mod program;
+pub mod unicode_tables;
+mod utf8;
 
 // Starter code for PS06 - thegrep
 use self::State::*;
+pub use self::backtrack::Backtrack;
 /**
  * Author(s): Daniel Evora, Peter Morrow
  * Onyen(s): devora, peterjm
@@ -10,9 +17,14 @@ use self::State::*;
  * on this assignment. I further pledge not to distribute my solution
  * to this code to anyone other than the course staff and partner.
  */
-use super::parser::Parser;
-use super::parser::AST;
-use super::tokenizer::Tokenizer;
+use self::charset::CharSet;
+use self::literal::Finder;
+use self::program::Program;
+use super::matcher::{FindIter, MatchKind, Matcher};
+use super::parser::{catenation, character, Flags, Greed, ParseError, Parser, AST};
+use super::matcher::is_word_char;
+use super::tokenizer::{Assertion, Bracket, BracketItem, Class, ClassKind, Look, PosixClass, Tokenizer};
+use std::sync::OnceLock;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 
@@ -22,141 +34,413 @@ use std::ops::Add;
 /**
  * ===== Public API =====
  */
-
 /**
  * An NFA is represented by an arena Vec of States
- * and a start state.
+ * and a start state, along with the byte-level
+ * Program it is compiled into for matching input
+ * and a prefilter for text every match contains.
+ * An NFA built from several patterns has an End
+ * state for each of them, in ends, and groups is
+ * the most groups any one of them has, with the
+ * numbers of those that have names in names. Its kind is
+ * leftmost-first when any of them has a lazy
+ * quantifier in it.
  */
 #[derive(Debug, Clone)]
 pub struct NFA {
     start: StateId,
     states: Vec<State>,
+    ends: Vec<StateId>,
+    groups: usize,
+    names: Vec<(String, usize)>,
+    kind: MatchKind,
+    program: Program,
+    prefilter: Option<Finder>,
 }
 
 impl NFA {
     /**
      * Construct an NFA from a regular expression pattern.
      */
-    pub fn from(regular_expression: &str) -> Result<NFA, String> {
+    pub fn from(regular_expression: &str) -> Result<NFA, ParseError> {
+        NFA::with_flags(regular_expression, Flags::default())
+    }
+
+    /**
+     * Construct an NFA from a regular expression pattern with flags
+     * set for the whole pattern, like case insensitivity for -i.
+     */
+    pub fn with_flags(regular_expression: &str, flags: Flags) -> Result<NFA, ParseError> {
+        NFA::with_patterns(&[regular_expression], flags).map_err(|(_, e)| e)
+    }
+
+    /**
+     * Construct a single NFA that matches wherever any of several
+     * patterns does, so that they are all searched for at once. Each
+     * pattern has an End state of its own to tell which of them matched.
+     * An empty pattern, like -e '' or a blank line of a -f file, matches
+     * everywhere, as it does in grep. An error comes with the index of the
+     * pattern it is in.
+     */
+    pub fn with_patterns<P: AsRef<str>>(
+        patterns: &[P],
+        flags: Flags,
+    ) -> Result<NFA, (usize, ParseError)> {
+        // Parse the Abstract Syntax Tree of each Regular Expression
+        let asts = patterns
+            .iter()
+            .enumerate()
+            .map(|(i, pattern)| match pattern.as_ref() {
+                "" => Ok(None),
+                pattern => Parser::parse_with_flags(Tokenizer::new(pattern), flags)
+                    .map(Some)
+                    .map_err(|e| (i, e)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(NFA::with_asts(&asts, Flags::default()))
+    }
+
+    /**
+     * Construct a single NFA that matches wherever any of several
+     * strings occurs, taking every char in them literally, as for -F.
+     * An empty string matches everywhere.
+     */
+    pub fn with_literals<P: AsRef<str>>(literals: &[P], flags: Flags) -> NFA {
+        let asts: Vec<_> = literals
+            .iter()
+            .map(|literal| literal.as_ref().chars().map(character).reduce(catenation))
+            .collect();
+        NFA::with_asts(&asts, flags)
+    }
+
+    /**
+     * Construct the NFA for the union of asts, with None standing for a
+     * pattern that only matches the empty string.
+     */
+    fn with_asts(asts: &[Option<AST>], flags: Flags) -> NFA {
         let mut nfa = NFA::new();
 
         let start = nfa.add_state(Start(None));
         nfa.start = start;
 
-        // Parse the Abstract Syntax Tree of the Regular Expression
-        let ast = &Parser::parse(Tokenizer::new(regular_expression))?;
-        // The "body" of the NFA is made of the states between Start and End
-        let body = nfa.gen_fragment(ast);
-        nfa.join(nfa.start, body.start);
+        let mut last = start;
+        for (i, ast) in asts.iter().enumerate() {
+            // The "body" of the NFA is made of the states between Start and End
+            let body = match ast {
+                Some(ast) => nfa.gen_fragment(ast, flags),
+                None => {
+                    let state = nfa.add_state(Start(None));
+                    Fragment {
+                        start: state,
+                        ends: vec![state],
+                    }
+                }
+            };
+            // every pattern but the last is one leg of a Split whose other leg leads to the rest
+            let entry = if i + 1 < asts.len() {
+                nfa.add_state(Split(Some(body.start), None))
+            } else {
+                body.start
+            };
+            nfa.join(last, entry);
+            last = entry;
 
-        let end = nfa.add_state(End);
-        nfa.join_fragment(&body, end);
+            let end = nfa.add_state(End);
+            nfa.join_fragment(&body, end);
+            nfa.ends.push(end);
+        }
+        if asts.is_empty() {
+            // with no patterns there is nothing to match, but an End keeps the NFA well formed
+            let never = nfa.add_state(Match(Char::Set(CharSet::new(vec![]), false), None));
+            nfa.join(start, never);
+            let end = nfa.add_state(End);
+            nfa.join(never, end);
+        }
 
-        Ok(nfa)
+        nfa.program = Program::new(&nfa);
+        // an empty pattern matches without containing anything
+        if asts.iter().all(Option::is_some) {
+            nfa.prefilter = literal::required(asts.iter().flatten(), flags).map(Finder::new);
+        }
+        nfa
     }
 
     /**
      * Given an input string, simulate the NFA to determine if the
      * input is accepted by the input string.
      */
-
     pub fn accepts(&self, input: &str) -> bool {
-        let curr_state = self.start; // sets the current state to the start
-        let chars = input.chars(); // creating an iterator over the characters of the input
-        self.recur(curr_state, chars) // calls recursive helper function where StateId and iterator state are kept track of
-    }
-
-    pub fn recur(&self, mut curr_state: StateId, mut chars: std::str::Chars) -> bool {
-        match &self.states[curr_state] {
-            // matches the current state to one of State's enums
-            State::Start(Some(id)) => {
-                // if the curr_state is the Start state, this is matched
-                curr_state = *id; // curr_state is now set to whatever state self.start points to
-                self.recur(curr_state, chars) // recusrive call to start testing input from state right after the start state
-            }
-            State::Match(expected_char, Some(id)) => match expected_char {
-                // if the curr_state is a Match state, this is matched
-                Char::Literal(c) => {
-                    if let Some(letter) = chars.next() {
-                        // checks to make sure there is something left in input and moves forward on iterator
-                        if letter == *c {
-                            // checks to see if the next letter in input is equal to the character matched by c
-                            curr_state = *id; // curr_state changes to wherever curr_state points to
-                            self.recur(curr_state, chars) // recursive call
-                        } else {
-                            // self.recur(self.start, chars)
-                            false
-                        }
-                    } else {
-                        false // false if the input ends and there was never a match
-                    }
-                }
-                Char::Any => {
-                    curr_state = *id; // curr_state is wherever curr_state currently points to
-                    if let Some(c) = chars.next() {
-                        // moves iterator to nect letter in input
-                        self.recur(curr_state, chars)
-                    } else {
-                        false
-                    } // recursive call
-                }
-            },
-            State::Split(Some(leg_one), Some(leg_two)) => {
-                let clone = chars.clone(); // clones iterator since chars is mutable and we need to test two (or more) possibilities for chars
-                self.recur(*leg_one, chars) || self.recur(*leg_two, clone)
-            }
-            State::End => chars.next() == None, // if the State is the End state, we know that the input is accepted (base case here)
-            _ => false,         // if there is any other state, that means return false
+        self.accepts_bytes(input.as_bytes())
+    }
+
+    /**
+     * Given raw input bytes, simulate the NFA to determine if they are
+     * accepted. The bytes do not need to be valid UTF-8: AnyChar and
+     * literals match the UTF-8 encoding of a char, and a byte that is
+     * not part of a valid encoding is matched by AnyChar, but by no
+     * literal.
+     */
+    pub fn accepts_bytes(&self, input: &[u8]) -> bool {
+        self.program.accepts(input)
+    }
+
+    /**
+     * Determine whether the NFA accepts any substring of haystack,
+     * which is how a line is matched when searching.
+     */
+    pub fn is_match(&self, haystack: &[u8]) -> bool {
+        self.might_match(haystack) && self.program.is_match(haystack)
+    }
+
+    /**
+     * Find the leftmost match in haystack, the longest or the first one
+     * by the NFA's kind, returning the byte offsets of its start and end.
+     */
+    pub fn find(&self, haystack: &[u8]) -> Option<(usize, usize)> {
+        self.find_at(haystack, 0)
+    }
+
+    /**
+     * Find the leftmost match in haystack that starts at or after the
+     * offset at.
+     */
+    pub fn find_at(&self, haystack: &[u8], at: usize) -> Option<(usize, usize)> {
+        if !self.might_match(&haystack[at..]) {
+            return None;
+        }
+        match self.kind {
+            MatchKind::LeftmostLongest => self.program.find_at(haystack, at),
+            MatchKind::LeftmostFirst => self.program.find_first_at(haystack, at),
+        }
+    }
+
+    /**
+     * Where each group matched in the match from start to end, as the
+     * Matcher trait's captures are.
+     */
+    pub fn captures(&self, haystack: &[u8], start: usize, end: usize) -> Vec<Option<usize>> {
+        let mut captures = self
+            .program
+            .captures(haystack, start, end, 2 * (self.groups + 1))
+            .unwrap_or_else(|| vec![None; 2 * (self.groups + 1)]);
+        captures[0] = Some(start);
+        captures[1] = Some(end);
+        captures
+    }
+
+    /**
+     * The number of the group called name. When patterns built into one
+     * NFA have groups of the same name, the first pattern's is used.
+     */
+    pub fn group_index(&self, name: &str) -> Option<usize> {
+        self.names.iter().find(|(n, _)| n == name).map(|(_, index)| *index)
+    }
+
+    /**
+     * The Matcher to search with for the NFA: itself, or a Backtrack
+     * matcher when it has backreferences or lookaround, since those are
+     * more than its Program can match and only patterns with them should
+     * pay for backtracking.
+     */
+    pub fn into_matcher(self) -> Box<dyn Matcher> {
+        if self.needs_backtracking() {
+            Box::new(Backtrack::new(self))
+        } else {
+            Box::new(self)
+        }
+    }
+
+    /**
+     * Whether the matches the NFA finds are the longest or the first
+     * ones at the leftmost start.
+     */
+    pub fn match_kind(&self) -> MatchKind {
+        self.kind
+    }
+
+    /**
+     * The offset of the next place at or after at where a match could
+     * be, which is where the text every match contains next occurs, so
+     * lines before it can be skipped without running the automaton on
+     * them. Without any such text there is nothing cheap to skip with,
+     * and at itself is the candidate: running the automaton over the rest
+     * of haystack instead would cross lines, and for a pattern that can
+     * match newlines, scan to the end of it from every line.
+     */
+    pub fn candidate(&self, haystack: &[u8], at: usize) -> Option<usize> {
+        match &self.prefilter {
+            Some(finder) => finder.find(&haystack[at..]).map(|i| at + i),
+            None => Some(at),
+        }
+    }
+
+    /**
+     * The indexes of the patterns the NFA was built from that match
+     * anywhere within haystack, in order.
+     */
+    pub fn which(&self, haystack: &[u8]) -> Vec<usize> {
+        if !self.might_match(haystack) {
+            return vec![];
         }
+        let matched = self.program.matches(haystack);
+        (0..self.ends.len())
+            .filter(|i| matched.contains(&self.ends[*i]))
+            .collect()
+    }
+
+    /**
+     * The ends of the matches that start at the offset start, in order,
+     * each with the index of the pattern it is a match of.
+     */
+    pub fn matches_at(&self, haystack: &[u8], start: usize) -> Vec<(usize, usize)> {
+        self.program
+            .matches_at(haystack, start)
+            .into_iter()
+            .filter_map(|(end, id)| self.ends.iter().position(|e| *e == id).map(|i| (end, i)))
+            .collect()
+    }
+
+    /**
+     * Whether the NFA has backreferences or lookaround in it. The Program
+     * it searches with cannot match either, so it has to be searched with
+     * a Backtrack matcher instead.
+     */
+    pub fn needs_backtracking(&self) -> bool {
+        self.states.iter().any(|state| matches!(state, Backref(..) | LookAround(..)))
+    }
+
+    /**
+     * Whether the NFA has lookaround in it, which gen cannot generate
+     * strings for.
+     */
+    pub fn has_lookaround(&self) -> bool {
+        self.states.iter().any(|state| matches!(state, LookAround(..)))
+    }
+
+    /**
+     * Whether haystack contains the text that every match contains.
+     */
+    fn might_match(&self, haystack: &[u8]) -> bool {
+        match &self.prefilter {
+            Some(finder) => finder.find(haystack).is_some(),
+            None => true,
+        }
+    }
+
+    /**
+     * Iterate over the successive non-overlapping leftmost
+     * matches in haystack, as the byte offsets of their starts and ends.
+     */
+    pub fn find_iter<'n, 'h>(&'n self, haystack: &'h [u8]) -> FindIter<'n, 'h> {
+        FindIter::new(self, haystack)
     }
 
     /**
      * Gen function generates acceptable strings given a regular expression. 
      * recur_gen is a recursive helper method used in gen
      */
-
     pub fn gen(&self) -> String { //function that will call recursive function to generate string
         let start = self.start; 
-        let mut input = String::new(); //creates a new string
-        self.recur_gen(start, input) //calls recursive function
+        let input = String::new(); //creates a new string
+        let mut slots = vec![None; 2 * (self.groups + 1)]; //where each group starts and ends in input
+        self.recur_gen(start, input, &mut slots) //calls recursive function
     }
 
-    pub fn recur_gen(&self, mut curr_state: StateId, mut input: String) -> String {
+    pub fn recur_gen(&self, mut curr_state: StateId, mut input: String, slots: &mut [Option<usize>]) -> String {
         match &self.states[curr_state] { //matches states in NFA
             State::Start(Some(id)) => { //if its a start, moves to the next state
                 curr_state = *id;
-                self.recur_gen(curr_state, input)
+                self.recur_gen(curr_state, input, slots)
             }
             State::Match(expected_char, Some(id)) => match expected_char {
                 Char::Literal(c) => { //if its a match with a specified character, it adds this to the string
                     curr_state = *id;
                     input.push(*c);
-                    self.recur_gen(curr_state, input)
+                    self.recur_gen(curr_state, input, slots)
                 }
-                Char::Any => { //if its a match with AnyChar, adds a random char to the string
+                Char::Any(_) => { //if its a match with AnyChar, adds a random char to the string
                     curr_state = *id;
                     let mut rng = thread_rng();
                     let c: char = rng.sample(&Alphanumeric);
                     input.push(c);
-                    self.recur_gen(curr_state, input)
+                    self.recur_gen(curr_state, input, slots)
+                }
+                Char::Set(set, _) => { //if its a match with a set of chars, adds one of them at random
+                    curr_state = *id;
+                    //printable ASCII is preferred so that classes like \W come out readable
+                    let ascii = set.ascii();
+                    let set = if ascii.len() > 0 { &ascii } else { set };
+                    input.push(set.sample(&mut thread_rng()));
+                    self.recur_gen(curr_state, input, slots)
                 }
             },
             State::Split(Some(leg_one), Some(leg_two)) => { //if its a split, it will randomly choose which path to take
                 let choice: f64 = rand::thread_rng().gen();
                 if choice < 0.5 {
                     curr_state = *leg_one;
-                    self.recur_gen(curr_state, input)
+                    self.recur_gen(curr_state, input, slots)
                 } else {
                     curr_state = *leg_two;
-                    self.recur_gen(curr_state, input)
+                    self.recur_gen(curr_state, input, slots)
                 }
             }
+            State::Assert(_, Some(id)) => { //assertions take up no input, so it moves on past them
+                curr_state = *id;
+                self.recur_gen(curr_state, input, slots)
+            }
+            State::Save(slot, Some(id)) => { //notes where a group starts or ends in the string so far
+                slots[*slot] = Some(input.len());
+                curr_state = *id;
+                self.recur_gen(curr_state, input, slots)
+            }
+            State::Backref(group, _, Some(id)) => { //repeats what the group it refers to came out as
+                if let (Some(start), Some(end)) = (slots[2 * group], slots[2 * group + 1]) {
+                    let text = input[start..end].to_string();
+                    input.push_str(&text);
+                }
+                curr_state = *id;
+                self.recur_gen(curr_state, input, slots)
+            }
             State::End => input, //if it has reached the end of the NFA, returns the string
             _ => panic!("Unexpected state in NFA"),
         }
     }
 }
 
+impl Matcher for NFA {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        NFA::is_match(self, haystack)
+    }
+
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<(usize, usize)> {
+        NFA::find_at(self, haystack, at)
+    }
+
+    fn candidate(&self, haystack: &[u8], at: usize) -> Option<usize> {
+        NFA::candidate(self, haystack, at)
+    }
+
+    fn which(&self, haystack: &[u8]) -> Vec<usize> {
+        NFA::which(self, haystack)
+    }
+
+    fn matches_at(&self, haystack: &[u8], start: usize) -> Vec<(usize, usize)> {
+        NFA::matches_at(self, haystack, start)
+    }
+
+    fn accepts(&self, haystack: &[u8]) -> bool {
+        self.accepts_bytes(haystack)
+    }
+
+    fn captures(&self, haystack: &[u8], start: usize, end: usize) -> Vec<Option<usize>> {
+        NFA::captures(self, haystack, start, end)
+    }
+
+    fn group_index(&self, name: &str) -> Option<usize> {
+        NFA::group_index(self, name)
+    }
+}
+
 #[cfg(test)]
 mod public_api {
     use super::*;
@@ -168,84 +452,317 @@ mod public_api {
     #[test]
     fn simple() {
         let input = NFA::from("a").unwrap();
-        assert_eq!(input.accepts("a"), true);
-        assert_eq!(input.accepts("b"), false);
+        assert!(input.accepts("a"));
+        assert!(!input.accepts("b"));
     }
 
     #[test]
     fn catenation() {
         let input = NFA::from("abc").unwrap();
-        assert_eq!(input.accepts("abc"), true);
-        assert_eq!(input.accepts("abd"), false);
-        assert_eq!(input.accepts("adc"), false);
-        assert_eq!(input.accepts("dbc"), false);
-        assert_eq!(input.accepts("cba"), false);
+        assert!(input.accepts("abc"));
+        assert!(!input.accepts("abd"));
+        assert!(!input.accepts("adc"));
+        assert!(!input.accepts("dbc"));
+        assert!(!input.accepts("cba"));
     }
 
     #[test]
     fn simple_alternation() {
         let input = NFA::from("a|b").unwrap();
-        assert_eq!(input.accepts("a"), true);
-        assert_eq!(input.accepts("b"), true);
+        assert!(input.accepts("a"));
+        assert!(input.accepts("b"));
     }
 
     #[test]
     fn alt_with_cat() {
         let input = NFA::from("ab|ac").unwrap();
-        assert_eq!(input.accepts("ab"), true);
-        assert_eq!(input.accepts("ac"), true);
-        assert_eq!(input.accepts("bc"), false);
-        assert_eq!(input.accepts("bb"), false);
-        assert_eq!(input.accepts("cc"), false);
-        assert_eq!(input.accepts("aa"), false);
+        assert!(input.accepts("ab"));
+        assert!(input.accepts("ac"));
+        assert!(!input.accepts("bc"));
+        assert!(!input.accepts("bb"));
+        assert!(!input.accepts("cc"));
+        assert!(!input.accepts("aa"));
         let input = NFA::from("a|bc").unwrap();
-        assert_eq!(input.accepts("a"), true);
-        assert_eq!(input.accepts("bc"), true);
-        assert_eq!(input.accepts("bb"), false);
+        assert!(input.accepts("a"));
+        assert!(input.accepts("bc"));
+        assert!(!input.accepts("bb"));
     }
 
     #[test]
     fn multiple_alts() {
         let input = NFA::from("a|b|cd").unwrap();
-        assert_eq!(input.accepts("a"), true);
-        assert_eq!(input.accepts("b"), true);
-        assert_eq!(input.accepts("cd"), true);
-        assert_eq!(input.accepts("cc"), false);
+        assert!(input.accepts("a"));
+        assert!(input.accepts("b"));
+        assert!(input.accepts("cd"));
+        assert!(!input.accepts("cc"));
     }
 
     #[test]
     fn input_with_any() {
         let input = NFA::from("a...b").unwrap();
-        assert_eq!(input.accepts("ab"), false);
-        assert_eq!(input.accepts("a   b"), true);
-        assert_eq!(input.accepts("axyzb"), true);
-        assert_eq!(input.accepts("xyzb"), false);
-        assert_eq!(input.accepts("axyz"), false);
+        assert!(!input.accepts("ab"));
+        assert!(input.accepts("a   b"));
+        assert!(input.accepts("axyzb"));
+        assert!(!input.accepts("xyzb"));
+        assert!(!input.accepts("axyz"));
     }
 
     #[test]
     fn simple_closure() {
         let input = NFA::from("a*").unwrap();
-        assert_eq!(input.accepts(""), true);
-        assert_eq!(input.accepts("aaaaaaa"), true);
+        assert!(input.accepts(""));
+        assert!(input.accepts("aaaaaaa"));
     }
 
     #[test]
     fn more_closure() {
         let input = NFA::from("ab*|c*a").unwrap();
-        assert_eq!(input.accepts("a"), true);
-        assert_eq!(input.accepts("abbbbbbb"), true);
-        assert_eq!(input.accepts("ccccccca"), true);
-        assert_eq!(input.accepts("bbbbccccbbb"), false);
-        assert_eq!(input.accepts("aa"), false);
+        assert!(input.accepts("a"));
+        assert!(input.accepts("abbbbbbb"));
+        assert!(input.accepts("ccccccca"));
+        assert!(!input.accepts("bbbbccccbbb"));
+        assert!(!input.accepts("aa"));
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let input = NFA::from("caf.").unwrap();
+        assert!(input.accepts_bytes(b"caf\xe9"));
+        assert!(input.accepts_bytes("café".as_bytes()));
+        assert!(!input.accepts_bytes(b"caf\xe9\xe9"));
+        let input = NFA::from("a.b").unwrap();
+        assert!(input.accepts_bytes(b"a\xffb"));
+        assert!(!input.accepts_bytes(b"a\xff\xfeb"));
+        let input = NFA::from("é").unwrap();
+        assert!(!input.accepts_bytes(b"\xe9"));
+        assert!(!input.accepts_bytes(b"\xc3"));
+    }
+
+    #[test]
+    fn is_match() {
+        let input = NFA::from("error").unwrap();
+        assert!(input.is_match(b"caf\xe9 error"));
+        assert!(input.is_match(b"error"));
+        assert!(!input.is_match(b"err or"));
+        let input = NFA::from("a*").unwrap();
+        assert!(input.is_match(b""));
+    }
+
+    #[test]
+    fn find() {
+        let input = NFA::from("b+").unwrap();
+        assert_eq!(input.find(b"abbbc"), Some((1, 4)));
+        assert_eq!(input.find(b"ac"), None);
+        let input = NFA::from("a*").unwrap();
+        assert_eq!(input.find(b"baa"), Some((0, 0)));
+    }
+
+    #[test]
+    fn dot_all() {
+        let input = NFA::from("a.b").unwrap();
+        assert!(input.accepts("a-b"));
+        assert!(!input.accepts("a\nb"));
+        let input = NFA::from("(?s)a.b").unwrap();
+        assert!(input.accepts("a\nb"));
+        let input = NFA::from("(?s:.)(?x) . # any char but a newline").unwrap();
+        assert!(input.accepts("\n-"));
+        assert!(!input.accepts("-\n"));
+    }
+
+    #[test]
+    fn captures() {
+        let input = NFA::from("(?P<key>\\w+)=(\\w*)").unwrap();
+        assert_eq!(input.group_index("key"), Some(1));
+        assert_eq!(input.group_index("value"), None);
+        let captures = input.captures(b"a k=v", 2, 5);
+        assert_eq!(captures, vec![Some(2), Some(5), Some(2), Some(3), Some(4), Some(5)]);
+        let input = NFA::from("(a+?)(a*)").unwrap();
+        assert_eq!(input.find(b"aaa"), Some((0, 3)));
+        assert_eq!(input.captures(b"aaa", 0, 3)[2..], [Some(0), Some(1), Some(1), Some(3)]);
+        let sum = NFA::from("(a)").unwrap() + NFA::from("(?P<b>b)").unwrap();
+        assert_eq!(sum.group_index("b"), Some(2));
+    }
+
+    #[test]
+    fn lazy() {
+        let input = NFA::from("<.+?>").unwrap();
+        assert_eq!(input.match_kind(), MatchKind::LeftmostFirst);
+        let found: Vec<_> = input.find_iter(b"<b>x</b>").collect();
+        assert_eq!(found, vec![(0, 3), (4, 8)]);
+        let input = NFA::from("colou?r").unwrap();
+        assert_eq!(input.match_kind(), MatchKind::LeftmostLongest);
+        assert!(input.accepts("color"));
+        assert!(input.accepts("colour"));
+        assert!(!input.accepts("colouur"));
+        assert!(input.accepts(&input.gen()));
+    }
+
+    #[test]
+    fn prefilter() {
+        let input = NFA::from(".*timeout=(1|2)+s").unwrap();
+        assert_eq!(input.prefilter.as_ref().map(|f| f.needle()), Some(&b"timeout="[..]));
+        assert!(input.is_match(b"x timeout=12s"));
+        assert!(!input.is_match(b"x timeout=3s"));
+        assert_eq!(input.candidate(b"a\nb timeout=9", 1), Some(4));
+        assert_eq!(input.candidate(b"timeout", 0), None);
+        //with no text every match contains, every line is a candidate
+        let input = NFA::from("a|b").unwrap();
+        assert_eq!(input.candidate(b"xxb", 0), Some(0));
+        let input = NFA::from("\\D*[qr]").unwrap();
+        assert!(input.prefilter.is_none());
+        assert_eq!(input.candidate(b"x\nxx\nq", 2), Some(2));
+    }
+
+    #[test]
+    fn patterns() {
+        let input = NFA::with_patterns(&["error", "warn(ing)*", "fatal"], Flags::default()).unwrap();
+        assert!(input.is_match(b"a warning"));
+        assert!(input.is_match(b"fatal: no"));
+        assert!(!input.is_match(b"info"));
+        assert_eq!(input.which(b"error: warn"), vec![0, 1]);
+        assert_eq!(input.which(b"fatal error"), vec![0, 2]);
+        assert_eq!(input.which(b"info"), Vec::<usize>::new());
+        let input = NFA::with_patterns(&["ok", "(oops"], Flags::default());
+        assert_eq!(input.err().map(|(i, e)| (i, e.msg)), Some((1, String::from("unclosed '('"))));
+        let input = NFA::with_patterns::<&str>(&[], Flags::default()).unwrap();
+        assert!(!input.is_match(b"anything"));
+        assert!(!input.is_match(b""));
+        //-e '' matches every line, as does a blank line in a -f file
+        let input = NFA::with_patterns(&[""], Flags::default()).unwrap();
+        assert!(input.is_match(b"anything"));
+        assert!(input.is_match(b""));
+        let file = "error\n\nfatal";
+        let input = NFA::with_patterns(&file.lines().collect::<Vec<_>>(), Flags::default()).unwrap();
+        assert_eq!(input.which(b"info"), vec![1]);
+        assert_eq!(input.which(b"fatal"), vec![1, 2]);
+    }
+
+    #[test]
+    fn find_iter() {
+        let input = NFA::from("ab+").unwrap();
+        let found: Vec<_> = input.find_iter(b"ab abbb a abab").collect();
+        assert_eq!(found, vec![(0, 2), (3, 7), (10, 12), (12, 14)]);
+        let input = NFA::from("a*").unwrap();
+        let found: Vec<_> = input.find_iter(b"aab").collect();
+        assert_eq!(found, vec![(0, 2), (3, 3)]);
+        let input = NFA::from("é").unwrap();
+        assert_eq!(input.find_iter("éé".as_bytes()).count(), 2);
+    }
+
+    #[test]
+    fn word_boundaries() {
+        let input = NFA::from("\\bcat\\b").unwrap();
+        assert!(input.is_match(b"a cat."));
+        assert!(input.is_match(b"cat"));
+        assert!(!input.is_match(b"concat"));
+        assert!(!input.is_match(b"cats"));
+        assert!(!input.is_match("écat".as_bytes()));
+        assert_eq!(input.find(b"cats cat"), Some((5, 8)));
+        let input = NFA::from("\\Bat\\B").unwrap();
+        assert!(input.is_match(b"cats"));
+        assert!(!input.is_match(b"a cat"));
+        assert!(!input.is_match(b"at"));
+        let input = NFA::from("a\\b").unwrap();
+        assert!(input.accepts("a"));
+        assert_eq!(input.find_iter(b"aa a").collect::<Vec<_>>(), vec![(1, 2), (3, 4)]);
+        let input = NFA::from("\\b").unwrap();
+        assert!(!input.is_match(b"  "));
+        assert_eq!(input.find(b" x"), Some((1, 1)));
+    }
+
+    #[test]
+    fn classes() {
+        let input = NFA::from("\\d+-\\d+").unwrap();
+        assert!(input.accepts("2024-06"));
+        assert!(!input.accepts("20x4-06"));
+        let input = NFA::from("\\w+\\s\\S").unwrap();
+        assert!(input.accepts("naïve_1\u{3000}x"));
+        assert!(!input.accepts("a-b x"));
+        assert!(!input.accepts("ab  "));
+        let input = NFA::from("\\D\\W").unwrap();
+        assert!(input.accepts("a-"));
+        assert!(input.accepts("é\u{1F600}"));
+        assert!(!input.accepts("1-"));
+        assert!(!input.accepts("a_"));
+        //bytes that are not UTF-8 are only in negated classes
+        assert!(input.is_match(&[0xff, b'-']));
+        assert!(input.accepts_bytes(&[b'a', 0xff]));
+        let input = NFA::from("[^a][\\w]").unwrap();
+        assert!(input.accepts_bytes(&[0xff, b'b']));
+        assert!(!input.accepts_bytes(&[b'b', 0xff]));
+    }
+
+    #[test]
+    fn properties() {
+        let input = NFA::from("\\p{Greek}+ \\p{Nd}+").unwrap();
+        assert!(input.accepts("λόγος ٣٤"));
+        assert!(!input.accepts("logos 34x"));
+        let input = NFA::from("\\pL\\P{L}").unwrap();
+        assert!(input.accepts("ж!"));
+        assert!(!input.accepts("жж"));
+        let input = NFA::from("[\\p{Han}\\p{Hiragana}]+").unwrap();
+        assert!(input.accepts("日本ごです"));
+        assert!(!input.accepts("カタカナ"));
+        let input = NFA::with_flags("\\p{Lu}", Flags { case_insensitive: true, ..Flags::default() }).unwrap();
+        assert!(input.accepts("a"));
+        assert!(input.accepts("Ω"));
+        assert!(!input.accepts("1"));
+    }
+
+    #[test]
+    fn brackets() {
+        let input = NFA::from("[a-c_][^0-9]").unwrap();
+        assert!(input.accepts("b-"));
+        assert!(input.accepts("_é"));
+        assert!(!input.accepts("d-"));
+        assert!(!input.accepts("a5"));
+        let input = NFA::from("[[:alpha:]]+[[:digit:][:space:]]").unwrap();
+        assert!(input.accepts("naïve\u{A0}"));
+        assert!(input.accepts("abc1"));
+        assert!(!input.accepts("abc١"));
+        let input = NFA::from("[[:punct:]][[:upper:]][[:xdigit:]]").unwrap();
+        assert!(input.accepts("!ÉF"));
+        assert!(!input.accepts("«ÉF"));
+        assert!(!input.accepts("!ÉG"));
+        let input = NFA::with_flags("[^a-c]", Flags { case_insensitive: true, ..Flags::default() }).unwrap();
+        assert!(!input.accepts("B"));
+        assert!(input.accepts("d"));
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let input = NFA::from("(?i)error").unwrap();
+        assert!(input.is_match(b"ERROR: disk full"));
+        assert!(input.is_match(b"Error"));
+        assert!(!input.is_match(b"Err0r"));
+        let input = NFA::from("a(?i)b(?-i)c").unwrap();
+        assert!(input.accepts("aBc"));
+        assert!(!input.accepts("ABc"));
+        assert!(!input.accepts("aBC"));
+        let input = NFA::from("(a(?i)b)b").unwrap();
+        assert!(input.accepts("aBb"));
+        assert!(!input.accepts("aBB"));
+        let input = NFA::from("(?i)straße σ").unwrap();
+        assert!(input.accepts("STRAẞE Σ"));
+        assert!(input.accepts("straße ς"));
+        let input = NFA::with_flags(
+            "k",
+            Flags {
+                case_insensitive: true,
+                ..Flags::default()
+            },
+        )
+        .unwrap();
+        assert!(input.accepts("K"));
+        assert!(input.accepts("\u{212A}"));
     }
 
     #[test]
     fn one_or_more() {
         let input = NFA::from("a+").unwrap();
-        assert_eq!(input.accepts("a"), true);
-        assert_eq!(input.accepts("aaaaaaa"), true);
-        assert_eq!(input.accepts(""), false);
+        assert!(input.accepts("a"));
+        assert!(input.accepts("aaaaaaa"));
+        assert!(!input.accepts(""));
     }
 
     /** 
@@ -255,35 +772,50 @@ mod public_api {
     #[test]
     fn cat_gen() {
         let nfa = NFA::from("abc").unwrap();
-        assert_eq!(nfa.accepts(&nfa.gen()), true);
+        assert!(nfa.accepts(&nfa.gen()));
     }
 
     #[test]
     fn alt_gen() {
         let nfa = NFA::from("a|b|c").unwrap();
-        assert_eq!(nfa.accepts(&nfa.gen()), true);
+        assert!(nfa.accepts(&nfa.gen()));
     }
 
     #[test]
     fn clo_gen() {
         let nfa = NFA::from("(ab)*").unwrap();
-        assert_eq!(nfa.accepts(&nfa.gen()), true);
+        assert!(nfa.accepts(&nfa.gen()));
     }
     
+    #[test]
+    fn case_insensitive_gen() {
+        let nfa = NFA::from("(?i)hello, world").unwrap();
+        assert_eq!(nfa.gen().to_lowercase(), "hello, world");
+    }
+
+    #[test]
+    fn class_gen() {
+        let nfa = NFA::from("\\d\\D\\w\\W\\s\\S[^[:alnum:]][[:lower:]]\\p{Greek}\\P{L}").unwrap();
+        for _ in 0..20 {
+            let generated = nfa.gen();
+            assert!(nfa.accepts(&generated), "{:?}", generated);
+        }
+    }
+
     #[test]
     fn plus_gen() {
         let nfa = NFA::from("ab+").unwrap();
-        assert_eq!(nfa.accepts(&nfa.gen()), true);
+        assert!(nfa.accepts(&nfa.gen()));
     }
     
     #[test]
     fn crazy_input() {
         let nfa_1 = NFA::from("omg( loll*| ha(ha)*)*").unwrap();
-        assert_eq!(nfa_1.accepts(&nfa_1.gen()), true);
+        assert!(nfa_1.accepts(&nfa_1.gen()));
         let nfa_2 = NFA::from("(tarr*|heee*ll*ss*)").unwrap();
-        assert_eq!(nfa_2.accepts(&nfa_2.gen()), true);
+        assert!(nfa_2.accepts(&nfa_2.gen()));
         let nfa_3 = NFA::from("pass: s.a.f.e+").unwrap();
-        assert_eq!(nfa_3.accepts(&nfa_3.gen()), true);
+        assert!(nfa_3.accepts(&nfa_3.gen()));
     }
 
 }
@@ -295,9 +827,13 @@ impl Add for NFA {
     fn add(self, rhs: NFA) -> NFA {
         // clone self and rhs
         let mut lhs = self.clone();
-        let mut rhs_clone = rhs.clone();
+        let rhs_clone = rhs.clone();
         // use lhs states length as an offset to alter rhs' StateIds
         let offset = lhs.states.len() - 1;
+        // the End states of lhs built from several patterns all lead on to rhs as the last does
+        for id in lhs.ends.iter().filter(|id| **id != offset) {
+            lhs.states[*id] = State::Split(Some(offset), Some(offset));
+        }
         // pop the End state off of lhs
         lhs.states.pop();
         for s in &rhs_clone.states {
@@ -312,11 +848,40 @@ impl Add for NFA {
                 State::Split(Some(id_one), Some(id_two)) => {
                     lhs.states.push(State::Split(Some(id_one + offset), Some(id_two + offset)));
                 },
+                State::Assert(assertion, Some(id)) => {
+                    lhs.states.push(State::Assert(*assertion, Some(id + offset)));
+                },
+                // the groups of rhs are numbered on from those of lhs
+                State::Save(slot, Some(id)) => {
+                    lhs.states.push(State::Save(slot + 2 * lhs.groups, Some(id + offset)));
+                },
+                State::Backref(group, case_insensitive, Some(id)) => {
+                    lhs.states.push(State::Backref(group + lhs.groups, *case_insensitive, Some(id + offset)));
+                },
+                State::LookAround(look, body, width, Some(id)) => {
+                    lhs.states.push(State::LookAround(*look, body + offset, *width, Some(id + offset)));
+                },
+                State::LookEnd => lhs.states.push(State::LookEnd),
                 State::End => lhs.states.push(State::End),
                 _ => panic!("Unexpected state in NFA"),
             }
         }
-        println!("{:?}", lhs);
+        lhs.ends = rhs_clone.ends.iter().map(|id| id + offset).collect();
+        for (name, index) in &rhs_clone.names {
+            if lhs.group_index(name).is_none() {
+                lhs.names.push((name.clone(), index + lhs.groups));
+            }
+        }
+        lhs.groups += rhs_clone.groups;
+        if rhs_clone.kind == MatchKind::LeftmostFirst {
+            lhs.kind = MatchKind::LeftmostFirst;
+        }
+        lhs.program = Program::new(&lhs);
+        // matches of the sum contain what matches of either side do, so the longer literal is kept
+        let required = |nfa: &NFA| nfa.prefilter.as_ref().map_or(0, |f| f.needle().len());
+        if required(&rhs) > required(&lhs) {
+            lhs.prefilter = rhs.prefilter.clone();
+        }
         lhs
     }
 }
@@ -377,6 +942,16 @@ mod add_tests {
         assert!(!nfa.accepts("bcdd"));
     }
 
+    #[test]
+    fn lazy_add() {
+        let lhs = NFA::from("a+").unwrap();
+        let rhs = NFA::from("b??").unwrap();
+        let nfa = lhs + rhs;
+        assert_eq!(nfa.match_kind(), MatchKind::LeftmostFirst);
+        assert!(nfa.accepts("aab"));
+        assert_eq!(nfa.find(b"aab"), Some((0, 2)));
+    }
+
 }
 
 /**
@@ -389,6 +964,17 @@ type StateId = usize;
  * - Start is starting state
  * - Match is a state with a single matching transition out
  * - Split is a state with two epsilon transitions out
+ * - Assert is a state with an epsilon transition out that can only be
+ *   taken where its Assertion holds of the chars on either side
+ * - Save is a state with an epsilon transition out that records the
+ *   position in a slot, 2n where group n starts and 2n + 1 where it ends
+ * - Backref is a state that matches the text group n matched, ignoring
+ *   case when its flag is set
+ * - LookAround is a state with an epsilon transition out that can only
+ *   be taken where the states from its body on reach their LookEnd
+ *   matching the text right after it or before it, or do not if it is
+ *   negated; the Width of what the body matches bounds where a
+ *   lookbehind has to start
  * - End is the final accepting state
  */
 #[derive(Debug, Clone)]
@@ -396,6 +982,11 @@ enum State {
     Start(Option<StateId>),
     Match(Char, Option<StateId>),
     Split(Option<StateId>, Option<StateId>),
+    Assert(Assertion, Option<StateId>),
+    Save(usize, Option<StateId>),
+    Backref(usize, bool, Option<StateId>),
+    LookAround(Look, StateId, Width, Option<StateId>),
+    LookEnd,
     End,
 }
 
@@ -406,7 +997,214 @@ enum State {
 #[derive(Debug, Clone)]
 enum Char {
     Literal(char),
-    Any,
+    // whether '\n' is matched too, as it is under (?s)
+    Any(bool),
+    // whether a byte that is not part of a valid encoding is matched too, as it is by negated classes
+    Set(CharSet, bool),
+}
+
+impl Char {
+    /**
+     * The ranges of chars that can be taken across an edge with this label.
+     */
+    fn ranges(&self) -> Vec<(char, char)> {
+        match self {
+            Char::Literal(c) => vec![(*c, *c)],
+            Char::Any(true) => vec![('\0', std::char::MAX)],
+            Char::Any(false) => vec![('\0', '\t'), ('\u{b}', std::char::MAX)],
+            Char::Set(set, _) => set.ranges().to_vec(),
+        }
+    }
+
+    /**
+     * Whether c can be taken across an edge with this label.
+     */
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Char::Literal(literal) => *literal == c,
+            Char::Any(dot_all) => *dot_all || c != '\n',
+            Char::Set(set, _) => set.contains(c),
+        }
+    }
+
+    /**
+     * Whether a byte that does not begin a valid encoding can be taken
+     * across an edge with this label. Like the decoder that read input
+     * before it was matched as bytes, AnyChar takes one, and so does a
+     * class that leaves chars out rather than naming those it matches.
+     */
+    fn matches_invalid(&self) -> bool {
+        match self {
+            Char::Literal(_) => false,
+            Char::Any(_) => true,
+            Char::Set(_, invalid) => *invalid,
+        }
+    }
+}
+
+/**
+ * The label for an edge matching the char c. When matching is case
+ * insensitive, the edge is instead labeled with every char that c is
+ * equal to under simple case folding.
+ */
+fn literal(c: char, flags: Flags) -> Char {
+    if flags.case_insensitive {
+        let set = CharSet::new(vec![(c, c)]).case_fold();
+        if set.len() > 1 {
+            return Char::Set(set, false);
+        }
+    }
+    Char::Literal(c)
+}
+
+/**
+ * The set of chars a Class matches. Word and space characters are
+ * worked out from the Unicode properties std knows once, the first time
+ * they are needed, since that takes a pass over every char. Properties
+ * come with their ranges, and are the only classes that case folding
+ * changes: \p{Lu} with -i matches lower case letters too.
+ */
+fn class_set(class: Class, flags: Flags) -> CharSet {
+    static WORD: OnceLock<CharSet> = OnceLock::new();
+    let set = match class.kind {
+        ClassKind::Digit => posix_set(PosixClass::Digit),
+        ClassKind::Word => WORD.get_or_init(|| CharSet::matching(is_word_char)).clone(),
+        ClassKind::Space => posix_set(PosixClass::Space),
+        ClassKind::Property(property) if flags.case_insensitive => {
+            CharSet::new(property.ranges.to_vec()).case_fold()
+        }
+        ClassKind::Property(property) => CharSet::new(property.ranges.to_vec()),
+    };
+    if class.negated {
+        set.negate()
+    } else {
+        set
+    }
+}
+
+/**
+ * The set of chars a POSIX class matches. The ASCII classes are picked
+ * out of the first 128 chars, and the Unicode ones are worked out once
+ * like the word characters are.
+ */
+fn posix_set(class: PosixClass) -> CharSet {
+    static ALPHA: OnceLock<CharSet> = OnceLock::new();
+    static LOWER: OnceLock<CharSet> = OnceLock::new();
+    static SPACE: OnceLock<CharSet> = OnceLock::new();
+    static UPPER: OnceLock<CharSet> = OnceLock::new();
+    let ascii = |f: fn(&char) -> bool| CharSet::new(('\0'..='\x7F').filter(f).map(|c| (c, c)).collect());
+    match class {
+        PosixClass::Alnum => {
+            let alpha = posix_set(PosixClass::Alpha);
+            CharSet::new([alpha.ranges(), &[('0', '9')]].concat())
+        }
+        PosixClass::Alpha => ALPHA.get_or_init(|| CharSet::matching(char::is_alphabetic)).clone(),
+        PosixClass::Blank => CharSet::new(vec![(' ', ' '), ('\t', '\t')]),
+        PosixClass::Cntrl => ascii(char::is_ascii_control),
+        PosixClass::Digit => CharSet::new(vec![('0', '9')]),
+        PosixClass::Graph => ascii(char::is_ascii_graphic),
+        PosixClass::Lower => LOWER.get_or_init(|| CharSet::matching(char::is_lowercase)).clone(),
+        PosixClass::Print => CharSet::new(vec![(' ', '~')]),
+        PosixClass::Punct => ascii(char::is_ascii_punctuation),
+        PosixClass::Space => SPACE.get_or_init(|| CharSet::matching(char::is_whitespace)).clone(),
+        PosixClass::Upper => UPPER.get_or_init(|| CharSet::matching(char::is_uppercase)).clone(),
+        PosixClass::Xdigit => ascii(char::is_ascii_hexdigit),
+    }
+}
+
+/**
+ * The set of chars a bracket expression matches. Ignoring case adds the
+ * other cases of what the items match before a ^ takes them all away, so
+ * [^a] matches neither a nor A.
+ */
+fn bracket_set(bracket: &Bracket, flags: Flags) -> CharSet {
+    let mut ranges = vec![];
+    for item in &bracket.items {
+        match item {
+            BracketItem::Range(lo, hi) => ranges.push((*lo, *hi)),
+            BracketItem::Class(class) => ranges.extend_from_slice(class_set(*class, flags).ranges()),
+            BracketItem::Posix(class) => ranges.extend_from_slice(posix_set(*class).ranges()),
+        }
+    }
+    let mut set = CharSet::new(ranges);
+    if flags.case_insensitive {
+        set = set.case_fold();
+    }
+    if bracket.negated {
+        set.negate()
+    } else {
+        set
+    }
+}
+
+/**
+ * Whether a Bracket matches bytes that are not part of a valid encoding.
+ * Those are in a negated class like \D and in no other, so a bracket
+ * takes them when it has a negated class in it or when it is negated
+ * itself, but not both.
+ */
+fn bracket_invalid(bracket: &Bracket) -> bool {
+    let negated_class = bracket.items.iter().any(|item| matches!(item, BracketItem::Class(class) if class.negated));
+    negated_class != bracket.negated
+}
+
+/**
+ * The fewest bytes a match of some part of a pattern can take up, and
+ * the most, unless there is no most because it repeats or refers back.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Width {
+    min: usize,
+    max: Option<usize>,
+}
+
+/**
+ * The Width of the matches of an AST. Classes are taken to match chars
+ * of any length, from a byte that is not UTF-8 to a 4 byte encoding.
+ */
+fn width(ast: &AST, flags: Flags) -> Width {
+    let exactly = |n| Width { min: n, max: Some(n) };
+    match ast {
+        AST::Char(c) => {
+            let ranges = literal(*c, flags).ranges();
+            let lens = ranges.iter().flat_map(|(lo, hi)| vec![lo.len_utf8(), hi.len_utf8()]);
+            Width {
+                min: lens.clone().min().unwrap_or(0),
+                max: lens.max(),
+            }
+        }
+        AST::AnyChar | AST::Class(_) | AST::Bracket(_) => Width { min: 1, max: Some(4) },
+        AST::Assertion(_) | AST::Look(..) => exactly(0),
+        AST::Backref(_) => Width { min: 0, max: None },
+        AST::Flags(flags, e) => width(e, *flags),
+        AST::Group(_, _, e) => width(e, flags),
+        AST::Alternation(lhs, rhs) => {
+            let (lhs, rhs) = (width(lhs, flags), width(rhs, flags));
+            Width {
+                min: lhs.min.min(rhs.min),
+                max: lhs.max.zip(rhs.max).map(|(l, r)| l.max(r)),
+            }
+        }
+        AST::Catenation(lhs, rhs) => {
+            let (lhs, rhs) = (width(lhs, flags), width(rhs, flags));
+            Width {
+                min: lhs.min + rhs.min,
+                max: lhs.max.zip(rhs.max).map(|(l, r)| l + r),
+            }
+        }
+        AST::Optional(e, _) => Width {
+            min: 0,
+            ..width(e, flags)
+        },
+        // repeating something that can take up bytes has no most
+        AST::Closure(e, _) | AST::OneOrMore(e, _) => {
+            let body = width(e, flags);
+            Width {
+                min: if let AST::Closure(..) = ast { 0 } else { body.min },
+                max: body.max.filter(|max| *max == 0),
+            }
+        }
+    }
 }
 
 /**
@@ -430,7 +1228,13 @@ impl NFA {
     fn new() -> NFA {
         NFA {
             states: vec![],
+            ends: vec![],
             start: 0,
+            groups: 0,
+            names: vec![],
+            kind: MatchKind::default(),
+            program: Program::default(),
+            prefilter: None,
         }
     }
 
@@ -445,27 +1249,48 @@ impl NFA {
 
     /**
      * Given an AST node, this method returns a Fragment of the NFA
-     * representing it and its children.
+     * representing it and its children, under the flags in effect.
      */
-    fn gen_fragment(&mut self, ast: &AST) -> Fragment {
+    fn gen_fragment(&mut self, ast: &AST, flags: Flags) -> Fragment {
         match ast {
             AST::AnyChar => {
-                let state = self.add_state(Match(Char::Any, None));
+                let state = self.add_state(Match(Char::Any(flags.dot_all), None));
+                Fragment {
+                    start: state,
+                    ends: vec![state],
+                }
+            }
+            AST::Assertion(assertion) => {
+                let state = self.add_state(Assert(*assertion, None));
+                Fragment {
+                    start: state,
+                    ends: vec![state],
+                }
+            }
+            AST::Class(class) => {
+                let state = self.add_state(Match(Char::Set(class_set(*class, flags), class.negated), None));
+                Fragment {
+                    start: state,
+                    ends: vec![state],
+                }
+            }
+            AST::Bracket(bracket) => {
+                let state = self.add_state(Match(Char::Set(bracket_set(bracket, flags), bracket_invalid(bracket)), None));
                 Fragment {
                     start: state,
                     ends: vec![state],
                 }
             }
             AST::Char(c) => {
-                let state = self.add_state(Match(Char::Literal(*c), None));
+                let state = self.add_state(Match(literal(*c, flags), None));
                 Fragment {
                     start: state,
                     ends: vec![state],
                 }
             }
             AST::Catenation(lhs, rhs) => {
-                let frag_one = self.gen_fragment(&lhs);
-                let frag_two = self.gen_fragment(&rhs);
+                let frag_one = self.gen_fragment(lhs, flags);
+                let frag_two = self.gen_fragment(rhs, flags);
                 self.join_fragment(&frag_one, frag_two.start);
                 Fragment {
                     start: frag_one.start,
@@ -473,8 +1298,8 @@ impl NFA {
                 }
             }
             AST::Alternation(lhs, rhs) => {
-                let mut frag_one = self.gen_fragment(&lhs);
-                let mut frag_two = self.gen_fragment(&rhs);
+                let mut frag_one = self.gen_fragment(lhs, flags);
+                let mut frag_two = self.gen_fragment(rhs, flags);
                 let state = self.add_state(Split(Some(frag_one.start), Some(frag_two.start)));
                 frag_one.ends.append(&mut frag_two.ends);
                 Fragment {
@@ -482,9 +1307,9 @@ impl NFA {
                     ends: frag_one.ends,
                 }
             }
-            AST::Closure(expr) => {
-                let frag = self.gen_fragment(&expr);
-                let state = self.add_state(Split(Some(frag.start), None));
+            AST::Closure(expr, greed) => {
+                let frag = self.gen_fragment(expr, flags);
+                let state = self.repeat(frag.start, *greed);
                 self.join_fragment(&frag, state);
                 Fragment {
                     start: state,
@@ -494,16 +1319,69 @@ impl NFA {
 
             //implementation of one or more is similar to closure but the start is the beginning of
             //the frag instead of the state
-            AST::OneOrMore(expr) => { 
-                let frag = self.gen_fragment(&expr);
-                let state = self.add_state(Split(Some(frag.start), None));
+            AST::OneOrMore(expr, greed) => { 
+                let frag = self.gen_fragment(expr, flags);
+                let state = self.repeat(frag.start, *greed);
                 self.join_fragment(&frag, state);
                 Fragment {
                     start: frag.start,
                     ends: vec![state],
                 }
             }
-            
+
+            //an optional expr is a split that either goes through it or skips it
+            AST::Optional(expr, greed) => {
+                let mut frag = self.gen_fragment(expr, flags);
+                let state = self.repeat(frag.start, *greed);
+                frag.ends.push(state);
+                Fragment {
+                    start: state,
+                    ends: frag.ends,
+                }
+            }
+
+            AST::Flags(flags, expr) => self.gen_fragment(expr, *flags),
+
+            //a group is its contents between a pair of Save states for its start and end
+            AST::Group(index, name, expr) => {
+                if let Some(name) = name {
+                    if self.group_index(name).is_none() {
+                        self.names.push((name.clone(), *index));
+                    }
+                }
+                let open = self.add_state(Save(2 * index, None));
+                let frag = self.gen_fragment(expr, flags);
+                let close = self.add_state(Save(2 * index + 1, None));
+                self.join(open, frag.start);
+                self.join_fragment(&frag, close);
+                self.groups = self.groups.max(*index);
+                Fragment {
+                    start: open,
+                    ends: vec![close],
+                }
+            }
+
+            AST::Backref(index) => {
+                let state = self.add_state(Backref(*index, flags.case_insensitive, None));
+                Fragment {
+                    start: state,
+                    ends: vec![state],
+                }
+            }
+
+            //the body of a lookaround is a fragment of its own, off to the side of the path through
+            //the state for it
+            AST::Look(look, expr) => {
+                let body = self.gen_fragment(expr, flags);
+                let end = self.add_state(LookEnd);
+                self.join_fragment(&body, end);
+                let state = self.add_state(LookAround(*look, body.start, width(expr, flags), None));
+                Fragment {
+                    start: state,
+                    ends: vec![state],
+                }
+            }
+
         }
     }
 
@@ -516,18 +1394,39 @@ impl NFA {
         }
     }
 
+    /**
+     * Add the Split a quantifier starts or loops back through, with one
+     * leg into the body at body and the other left loose. The leg that
+     * is followed first is the one a match prefers: the body when greedy,
+     * the way on past it when lazy, which makes the NFA leftmost-first.
+     */
+    fn repeat(&mut self, body: StateId, greed: Greed) -> StateId {
+        match greed {
+            Greed::Greedy => self.add_state(Split(Some(body), None)),
+            Greed::Lazy => {
+                self.kind = MatchKind::LeftmostFirst;
+                self.add_state(Split(None, Some(body)))
+            }
+        }
+    }
+
     /**
      * Join a loose end of one state to another by IDs.
-     * Note in the Split case, only the 2nd ID (rhs) is being bound.
-     * It is assumed when building an NFA with these constructs
-     * that the lhs of an Split state will always be known and bound.
+     * Note in the Split case, only the leg that is still
+     * None is bound: the rhs of a greedy quantifier's
+     * Split and the lhs of a lazy one's.
      */
     fn join(&mut self, from: StateId, to: StateId) {
         match self.states[from] {
             Start(ref mut next) => *next = Some(to),
             Match(_, ref mut next) => *next = Some(to),
+            Split(ref mut lhs @ None, _) => *lhs = Some(to),
             Split(_, ref mut next) => *next = Some(to),
-            End => {}
+            Assert(_, ref mut next) => *next = Some(to),
+            Save(_, ref mut next) => *next = Some(to),
+            Backref(_, _, ref mut next) => *next = Some(to),
+            LookAround(_, _, _, ref mut next) => *next = Some(to),
+            LookEnd | End => {}
         }
     }
 }
@@ -0,0 +1,489 @@
+use super::charset::CharSet;
+use super::program::holds;
+use super::utf8;
+use super::{State, StateId, NFA};
+use crate::matcher::{decode, MatchKind, Matcher};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/**
+ * The most states a Backtrack matcher follows while searching for a
+ * match from one start in a haystack before it gives up on the haystack.
+ */
+const STEP_LIMIT: usize = 1 << 20;
+
+/**
+ * A Backtrack matcher searches an NFA by following one path through it
+ * at a time, trying the left leg of each Split before the right one and
+ * backing up to try the next path when one fails, much as thegrep first
+ * matched patterns. Unlike the Program, which follows every path at once,
+ * it knows where each group matched on the path it is on, which is what
+ * backreferences like \1 need, and it can stop on a path to search the
+ * body of a lookaround from there. Groups in a lookaround only keep what
+ * they matched until the end of it.
+ *
+ * The number of paths can grow exponentially with the length of the
+ * input, so it is only used for patterns that need it, and it
+ * gives up on a haystack after following STEP_LIMIT states from any one
+ * start in it. The budget is per start so that long lines, which have
+ * more starts to try, get a budget to match. A match
+ * it gave up looking for counts as no match, and gave_up tells whether
+ * that ever happened. Matches are leftmost-longest like everywhere else:
+ * every path from the leftmost start that has a match is followed to
+ * find the longest one.
+ */
+#[derive(Debug)]
+pub struct Backtrack {
+    nfa: NFA,
+    gave_up: AtomicBool,
+}
+
+/**
+ * The work left to do on the paths being followed. Following a Split or a
+ * Save changes what the paths through it see, and the Restore job pushed
+ * under the paths puts it back once they have all been tried.
+ */
+enum Job {
+    Follow(StateId, usize),
+    RestoreSlot(usize, Option<usize>),
+    RestoreEntered(StateId, usize),
+}
+
+impl Backtrack {
+    pub fn new(nfa: NFA) -> Backtrack {
+        Backtrack {
+            nfa,
+            gave_up: AtomicBool::new(false),
+        }
+    }
+
+    /**
+     * Follow every path through the NFA from the state from at the offset
+     * start, with the groups matched as in slots, calling found with the
+     * end, the End or LookEnd state and the groups of each match until it
+     * returns true. steps counts the states followed across searches from
+     * the same start, and false comes back if there were too many to finish.
+     */
+    fn search(
+        &self,
+        haystack: &[u8],
+        from: StateId,
+        start: usize,
+        mut slots: Vec<Option<usize>>,
+        steps: &mut usize,
+        mut found: impl FnMut(usize, StateId, &[Option<usize>]) -> bool,
+    ) -> bool {
+        // the offset each Split was last entered at on the path being followed. Coming back to
+        // one at the same offset means going around a loop that matched nothing, which no match
+        // needs, so that path is dropped
+        let mut entered = vec![usize::MAX; self.nfa.states.len()];
+        let mut jobs = vec![Job::Follow(from, start)];
+        while let Some(job) = jobs.pop() {
+            let (id, at) = match job {
+                Job::Follow(id, at) => (id, at),
+                Job::RestoreSlot(slot, old) => {
+                    slots[slot] = old;
+                    continue;
+                }
+                Job::RestoreEntered(id, old) => {
+                    entered[id] = old;
+                    continue;
+                }
+            };
+            *steps += 1;
+            if *steps > STEP_LIMIT {
+                return false;
+            }
+            match &self.nfa.states[id] {
+                State::Start(Some(next)) => jobs.push(Job::Follow(*next, at)),
+                State::Match(c, Some(next)) => match utf8::decode(&haystack[at..]) {
+                    Some((Some(ch), len)) if c.matches(ch) => jobs.push(Job::Follow(*next, at + len)),
+                    Some((None, len)) if c.matches_invalid() => jobs.push(Job::Follow(*next, at + len)),
+                    _ => {}
+                },
+                State::Split(Some(lhs), Some(rhs)) => {
+                    if entered[id] == at {
+                        continue;
+                    }
+                    jobs.push(Job::RestoreEntered(id, entered[id]));
+                    entered[id] = at;
+                    // the left leg goes on top so that it is tried first
+                    jobs.push(Job::Follow(*rhs, at));
+                    jobs.push(Job::Follow(*lhs, at));
+                }
+                State::Assert(assertion, Some(next)) if holds(*assertion, haystack, at) => {
+                    jobs.push(Job::Follow(*next, at))
+                }
+                State::Save(slot, Some(next)) => {
+                    jobs.push(Job::RestoreSlot(*slot, slots[*slot]));
+                    slots[*slot] = Some(at);
+                    jobs.push(Job::Follow(*next, at));
+                }
+                State::Backref(group, case_insensitive, Some(next)) => {
+                    // a group that has not matched on this path, or is still matching, matches
+                    // nothing for the backreference to repeat
+                    if let (Some(s), Some(e)) = (slots[2 * group], slots[2 * group + 1]) {
+                        if s > e {
+                            continue;
+                        }
+                        if let Some(len) = repeats(&haystack[s..e], &haystack[at..], *case_insensitive) {
+                            jobs.push(Job::Follow(*next, at + len));
+                        }
+                    }
+                }
+                State::LookAround(look, _, _, Some(next)) => {
+                    match self.looks(id, haystack, at, &slots, steps) {
+                        Some(matched) if matched != look.negated => jobs.push(Job::Follow(*next, at)),
+                        Some(_) => {}
+                        None => return false,
+                    }
+                }
+                State::End | State::LookEnd if found(at, id, &slots) => return true,
+                _ => {}
+            }
+        }
+        true
+    }
+
+    /**
+     * Whether the body of the LookAround state id matches at the offset
+     * at, starting there to look ahead or ending there to look behind, or
+     * None if the search gave up. A lookbehind is only tried from the
+     * starts its Width leaves room for, so one that matches a few bytes
+     * does not search back over the whole haystack.
+     */
+    fn looks(&self, id: StateId, haystack: &[u8], at: usize, slots: &[Option<usize>], steps: &mut usize) -> Option<bool> {
+        let (look, body, width) = match &self.nfa.states[id] {
+            State::LookAround(look, body, width, _) => (*look, *body, *width),
+            _ => unreachable!("only LookAround states look around"),
+        };
+        let starts = if !look.behind {
+            at..=at
+        } else if let Some(last) = at.checked_sub(width.min) {
+            width.max.map_or(0, |max| at.saturating_sub(max))..=last
+        } else {
+            return Some(false);
+        };
+        for start in starts.rev() {
+            let mut matched = false;
+            let finished = self.search(haystack, body, start, slots.to_vec(), steps, |end, _, _| {
+                matched = !look.behind || end == at;
+                matched
+            });
+            if !finished {
+                return None;
+            }
+            if matched {
+                return Some(true);
+            }
+        }
+        Some(false)
+    }
+
+    /**
+     * The groups before anything has matched.
+     */
+    fn slots(&self) -> Vec<Option<usize>> {
+        vec![None; 2 * (self.nfa.groups + 1)]
+    }
+
+    /**
+     * The ends of all of the matches that start at start, with the End
+     * states they reach, or None if the search gave up.
+     */
+    fn ends(&self, haystack: &[u8], start: usize) -> Option<Vec<(usize, StateId)>> {
+        let mut ends = vec![];
+        let finished = self.search(haystack, self.nfa.start, start, self.slots(), &mut 0, |end, id, _| {
+            ends.push((end, id));
+            false
+        });
+        if !finished {
+            self.gave_up.store(true, Ordering::Relaxed);
+            return None;
+        }
+        Some(ends)
+    }
+
+    /**
+     * The end of the first match the search finds that starts at start,
+     * which is the leftmost-first one there, or None if the search gave up.
+     */
+    fn first(&self, haystack: &[u8], start: usize) -> Option<Option<usize>> {
+        let mut first = None;
+        let finished = self.search(haystack, self.nfa.start, start, self.slots(), &mut 0, |end, _, _| {
+            first = Some(end);
+            true
+        });
+        if !finished {
+            self.gave_up.store(true, Ordering::Relaxed);
+            return None;
+        }
+        Some(first)
+    }
+
+    /**
+     * The index of the pattern an End state belongs to.
+     */
+    fn pattern(&self, end: StateId) -> usize {
+        self.nfa.ends.iter().position(|e| *e == end).unwrap()
+    }
+}
+
+impl Matcher for Backtrack {
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        if !self.nfa.might_match(haystack) {
+            return false;
+        }
+        for start in 0..=haystack.len() {
+            let mut matched = false;
+            let finished = self.search(haystack, self.nfa.start, start, self.slots(), &mut 0, |_, _, _| {
+                matched = true;
+                true
+            });
+            if !finished {
+                self.gave_up.store(true, Ordering::Relaxed);
+                return false;
+            }
+            if matched {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<(usize, usize)> {
+        if !self.nfa.might_match(&haystack[at..]) {
+            return None;
+        }
+        for start in at..=haystack.len() {
+            let end = match self.nfa.kind {
+                MatchKind::LeftmostLongest => self.ends(haystack, start)?.iter().map(|(end, _)| *end).max(),
+                MatchKind::LeftmostFirst => self.first(haystack, start)?,
+            };
+            if let Some(end) = end {
+                return Some((start, end));
+            }
+        }
+        None
+    }
+
+    // a backtracking search for the next match could run over many lines and give up part way,
+    // so without a prefilter the next line is the candidate and find_at only runs on lines
+    fn candidate(&self, haystack: &[u8], at: usize) -> Option<usize> {
+        self.nfa.candidate(haystack, at)
+    }
+
+    fn which(&self, haystack: &[u8]) -> Vec<usize> {
+        if !self.nfa.might_match(haystack) {
+            return vec![];
+        }
+        let mut which = vec![];
+        for start in 0..=haystack.len() {
+            match self.ends(haystack, start) {
+                Some(ends) => which.extend(ends.into_iter().map(|(_, id)| self.pattern(id))),
+                None => break,
+            }
+        }
+        which.sort_unstable();
+        which.dedup();
+        which
+    }
+
+    fn matches_at(&self, haystack: &[u8], start: usize) -> Vec<(usize, usize)> {
+        let mut matches: Vec<_> = self
+            .ends(haystack, start)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(end, id)| (end, self.pattern(id)))
+            .collect();
+        matches.sort_unstable();
+        matches.dedup();
+        matches
+    }
+
+    fn captures(&self, haystack: &[u8], start: usize, end: usize) -> Vec<Option<usize>> {
+        let mut captures = self.slots();
+        self.search(haystack, self.nfa.start, start, self.slots(), &mut 0, |at, _, slots| {
+            if at == end {
+                captures = slots.to_vec();
+            }
+            at == end
+        });
+        captures[0] = Some(start);
+        captures[1] = Some(end);
+        captures
+    }
+
+    fn group_index(&self, name: &str) -> Option<usize> {
+        self.nfa.group_index(name)
+    }
+
+    fn gave_up(&self) -> bool {
+        self.gave_up.load(Ordering::Relaxed)
+    }
+}
+
+/**
+ * The char that starts at the offset at and the length of its encoding.
+ */
+fn char_at(haystack: &[u8], at: usize) -> Option<(char, usize)> {
+    (1..=4.min(haystack.len() - at)).find_map(|len| decode(&haystack[at..at + len]).map(|c| (c, len)))
+}
+
+/**
+ * How much of the start of rest is a repeat of text, which with case
+ * ignored can be text in other cases, of another length.
+ */
+fn repeats(text: &[u8], rest: &[u8], case_insensitive: bool) -> Option<usize> {
+    if rest.starts_with(text) {
+        return Some(text.len());
+    }
+    if !case_insensitive {
+        return None;
+    }
+    let (mut i, mut at) = (0, 0);
+    while i < text.len() {
+        let (lhs, lhs_len) = char_at(text, i)?;
+        let (rhs, rhs_len) = char_at(rest, at)?;
+        if lhs != rhs && !CharSet::new(vec![(lhs, lhs)]).case_fold().contains(rhs) {
+            return None;
+        }
+        i += lhs_len;
+        at += rhs_len;
+    }
+    Some(at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Flags;
+
+    fn backtrack(pattern: &str) -> Backtrack {
+        Backtrack::new(NFA::from(pattern).unwrap())
+    }
+
+    #[test]
+    fn doubled_words() {
+        let doubled = backtrack("\\b(\\w+) \\1\\b");
+        //every start in a long word backs off one character at a time, which takes steps in
+        //proportion to the square of its length
+        let long = backtrack("(\\w+) \\1");
+        let line = format!("{} b", "a".repeat(3000));
+        assert!(!long.is_match(line.as_bytes()));
+        assert!(!long.gave_up());
+        assert_eq!(long.find(format!("{} b", line).as_bytes()), Some((3001, 3004)));
+        assert!(doubled.is_match(b"this is is a test"));
+        assert!(!doubled.is_match(b"this is a test"));
+        assert!(!doubled.is_match(b"this island"));
+        assert_eq!(doubled.find(b"it was the the end"), Some((7, 14)));
+        assert_eq!(doubled.find("très très".as_bytes()), Some((0, 11)));
+    }
+
+    #[test]
+    fn groups() {
+        let repeated = backtrack("(a|b)(c|d)\\2\\1");
+        assert!(repeated.accepts(b"acca"));
+        assert!(repeated.accepts(b"bddb"));
+        assert!(!repeated.accepts(b"acda"));
+        //a group in a loop refers to what it matched last time around
+        let last = backtrack("((a|b)*)-\\2");
+        assert!(last.accepts(b"ab-b"));
+        assert!(!last.accepts(b"ab-a"));
+        //a group that did not match matches nothing
+        let unset = backtrack("(a)|b\\1");
+        assert!(unset.is_match(b"a"));
+        assert!(!unset.is_match(b"b"));
+        //a byte that is not UTF-8 is matched by . and repeated as it is
+        let invalid = backtrack("(.)-\\1");
+        assert!(invalid.accepts(b"\xff-\xff"));
+        assert!(!invalid.accepts(b"\xff-\xfe"));
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let nfa = NFA::with_flags("(ab)\\1", Flags { case_insensitive: true, ..Flags::default() }).unwrap();
+        let folded = Backtrack::new(nfa);
+        assert!(folded.accepts(b"abAB"));
+        assert!(folded.accepts(b"AbaB"));
+        assert!(!folded.accepts(b"abAc"));
+        assert!(backtrack("(é)\\1").is_match("éé".as_bytes()));
+        assert!(!backtrack("(é)\\1").is_match("éÉ".as_bytes()));
+    }
+
+    #[test]
+    fn longest() {
+        let longest = backtrack("(a+)(b*)\\1");
+        assert_eq!(longest.find(b"xaabaa"), Some((1, 6)));
+        assert_eq!(longest.matches_at(b"aaaa", 0), vec![(2, 0), (4, 0)]);
+        let patterns = NFA::with_patterns(&["(a)\\1", "(b)\\1"], Flags::default()).unwrap();
+        let patterns = Backtrack::new(patterns);
+        assert_eq!(patterns.which(b"bb aa"), vec![0, 1]);
+        assert_eq!(patterns.which(b"ab"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn lazy() {
+        let first = backtrack("(\"|').*?\\1");
+        assert_eq!(first.find(b"say \"hi\" and \"bye\""), Some((4, 8)));
+        let longest = backtrack("(a|ab)(?=b)");
+        let lazy = backtrack("(a|ab)(?=b)a*?");
+        assert_eq!(longest.find(b"abb"), Some((0, 2)));
+        assert_eq!(lazy.find(b"abb"), Some((0, 1)));
+    }
+
+    #[test]
+    fn lookaround() {
+        let ahead = backtrack("\\w+(?=!)");
+        assert_eq!(ahead.find(b"hey you!"), Some((4, 7)));
+        assert!(!ahead.is_match(b"hey you?"));
+        let not_ahead = backtrack("foo(?!bar)");
+        assert_eq!(not_ahead.find(b"foobar foobaz"), Some((7, 10)));
+        let behind = backtrack("(?<=\\$)\\d+");
+        assert_eq!(behind.find(b"42 items at $15"), Some((13, 15)));
+        let not_behind = backtrack("(?<!-)\\b\\d+");
+        assert_eq!(not_behind.find(b"-3 or 4"), Some((6, 7)));
+        //lookbehind of any length, and groups matched before it are seen inside of it
+        let repeat = backtrack("(a+)b(?<=\\1b)c");
+        assert!(repeat.is_match(b"aabc"));
+        let nested = backtrack("x(?=y(?!z))");
+        assert!(nested.is_match(b"xyw"));
+        assert!(!nested.is_match(b"xyz"));
+        //a lookbehind of a few bytes only looks a few bytes back, however long the line is
+        let short = backtrack("(?<=a)b");
+        let line = format!("{}ab", "x".repeat(6000));
+        assert_eq!(short.find(line.as_bytes()), Some((6001, 6002)));
+        assert!(!short.is_match("x".repeat(6000).as_bytes()));
+        assert!(!short.gave_up());
+        let alternatives = backtrack("(?<=ab|[éa]|\\bc*)d");
+        assert!(alternatives.is_match("éd".as_bytes()));
+        assert!(alternatives.is_match(b"abd"));
+        assert!(alternatives.is_match(b" cccd"));
+        assert!(!alternatives.is_match(b"xbd"));
+    }
+
+    #[test]
+    fn empty_loops() {
+        let empty = backtrack("((a*)*)\\1b");
+        assert!(empty.is_match(b"aab"));
+        assert!(empty.is_match(b"b"));
+        assert!(!empty.gave_up());
+    }
+
+    #[test]
+    fn gives_up() {
+        //there are 2^40 ways for the loop to match the a's, and none of them is followed by y
+        let slow = backtrack("x(a|a)*y");
+        let haystack = format!("x{}zy", "a".repeat(40));
+        assert!(!slow.is_match(haystack.as_bytes()));
+        assert!(slow.gave_up());
+    }
+
+    #[test]
+    fn gen() {
+        let nfa = NFA::from("(a|b|c)+-(\\d)\\2\\1").unwrap();
+        for _ in 0..20 {
+            let generated = nfa.gen();
+            assert!(Backtrack::new(nfa.clone()).accepts(generated.as_bytes()), "{:?}", generated);
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use super::unicode_tables::CASE_FOLDING_SIMPLE;
+use rand::Rng;
+
+/**
+ * A CharSet is a set of chars stored as sorted, non-overlapping and
+ * non-adjacent inclusive ranges. It labels NFA edges that can be taken
+ * by any one of several chars, like the case variants of a letter.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct CharSet {
+    ranges: Vec<(char, char)>,
+}
+
+impl CharSet {
+    /**
+     * Build a set out of ranges given in any order, merging any that
+     * overlap or touch.
+     */
+    pub fn new(mut ranges: Vec<(char, char)>) -> CharSet {
+        ranges.sort();
+        let mut merged: Vec<(char, char)> = vec![];
+        for (lo, hi) in ranges {
+            if let Some(last) = merged.last_mut() {
+                if lo as u32 <= last.1 as u32 + 1 {
+                    last.1 = std::cmp::max(last.1, hi);
+                    continue;
+                }
+            }
+            merged.push((lo, hi));
+        }
+        CharSet { ranges: merged }
+    }
+
+    /**
+     * The set of every char that f holds for.
+     */
+    pub fn matching(f: impl Fn(char) -> bool) -> CharSet {
+        let mut ranges: Vec<(char, char)> = vec![];
+        let chars = ('\0'..='\u{D7FF}').chain('\u{E000}'..=std::char::MAX);
+        for c in chars.filter(|c| f(*c)) {
+            match ranges.last_mut() {
+                Some(last) if last.1 as u32 + 1 == c as u32 => last.1 = c,
+                _ => ranges.push((c, c)),
+            }
+        }
+        CharSet { ranges }
+    }
+
+    /**
+     * The set of every char that is not in this one.
+     */
+    pub fn negate(&self) -> CharSet {
+        let mut ranges = vec![];
+        let mut next = Some('\0');
+        for &(lo, hi) in &self.ranges {
+            if let Some(from) = next.filter(|from| *from < lo) {
+                ranges.push((from, before(lo)));
+            }
+            next = after(hi);
+        }
+        if let Some(from) = next {
+            ranges.push((from, std::char::MAX));
+        }
+        CharSet { ranges }
+    }
+
+    /**
+     * The printable ASCII chars in the set.
+     */
+    pub fn ascii(&self) -> CharSet {
+        let ranges = self
+            .ranges
+            .iter()
+            .filter(|&&(lo, hi)| lo <= '~' && hi >= ' ')
+            .map(|&(lo, hi)| (lo.max(' '), hi.min('~')))
+            .collect();
+        CharSet { ranges }
+    }
+
+    pub fn ranges(&self) -> &[(char, char)] {
+        &self.ranges
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        self.ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi)
+    }
+
+    /**
+     * The number of chars in the set.
+     */
+    pub fn len(&self) -> u32 {
+        self.ranges
+            .iter()
+            .map(|&(lo, hi)| hi as u32 - lo as u32 + 1)
+            .sum()
+    }
+
+    /**
+     * Add every char that is equal to a char in the set under Unicode
+     * simple case folding, like 'K' and the Kelvin sign for 'k'.
+     */
+    pub fn case_fold(&self) -> CharSet {
+        let mut ranges = self.ranges.clone();
+        for &(lo, hi) in &self.ranges {
+            let first = match CASE_FOLDING_SIMPLE.binary_search_by_key(&lo, |&(c, _)| c) {
+                Ok(i) | Err(i) => i,
+            };
+            for &(c, others) in CASE_FOLDING_SIMPLE[first..].iter() {
+                if c > hi {
+                    break;
+                }
+                ranges.extend(others.iter().map(|&o| (o, o)));
+            }
+        }
+        CharSet::new(ranges)
+    }
+
+    /**
+     * Pick a char out of the set at random, every char being as likely
+     * as any other.
+     */
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> char {
+        let mut n = rng.gen_range(0, self.len());
+        for &(lo, hi) in &self.ranges {
+            let size = hi as u32 - lo as u32 + 1;
+            if n < size {
+                // ranges are built from chars, so the ones in between are chars too unless
+                // the range straddles the surrogates, which are skipped over
+                return std::char::from_u32(lo as u32 + n).unwrap_or(hi);
+            }
+            n -= size;
+        }
+        unreachable!("sample is only taken from sets with chars in them")
+    }
+}
+
+/**
+ * The chars right before and after c, stepping over the surrogates,
+ * which are not chars.
+ */
+fn before(c: char) -> char {
+    match c {
+        '\u{E000}' => '\u{D7FF}',
+        c => std::char::from_u32(c as u32 - 1).unwrap(),
+    }
+}
+
+fn after(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        c => std::char::from_u32(c as u32 + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::thread_rng;
+
+    #[test]
+    fn merges_ranges() {
+        let set = CharSet::new(vec![('x', 'z'), ('a', 'c'), ('b', 'd'), ('e', 'e')]);
+        assert_eq!(set.ranges(), &[('a', 'e'), ('x', 'z')]);
+        assert_eq!(set.len(), 8);
+        assert!(set.contains('d'));
+        assert!(!set.contains('f'));
+    }
+
+    #[test]
+    fn case_fold() {
+        let set = CharSet::new(vec![('k', 'k')]).case_fold();
+        assert_eq!(set.ranges(), &[('K', 'K'), ('k', 'k'), ('\u{212A}', '\u{212A}')]);
+        let set = CharSet::new(vec![('Σ', 'Σ')]).case_fold();
+        assert_eq!(set.ranges(), &[('Σ', 'Σ'), ('ς', 'σ')]);
+        let set = CharSet::new(vec![('a', 'c'), ('1', '1')]).case_fold();
+        assert_eq!(set.ranges(), &[('1', '1'), ('A', 'C'), ('a', 'c')]);
+    }
+
+    #[test]
+    fn matching() {
+        let set = CharSet::matching(|c| c.is_ascii_digit() || c == '_');
+        assert_eq!(set.ranges(), &[('0', '9'), ('_', '_')]);
+        let set = CharSet::matching(|c| c > '\u{D000}' && c < '\u{F000}');
+        assert_eq!(set.ranges(), &[('\u{D001}', '\u{D7FF}'), ('\u{E000}', '\u{EFFF}')]);
+    }
+
+    #[test]
+    fn negate() {
+        let set = CharSet::new(vec![('0', '9'), ('_', '_')]).negate();
+        assert_eq!(
+            set.ranges(),
+            &[('\0', '/'), (':', '^'), ('`', std::char::MAX)]
+        );
+        assert_eq!(set.negate(), CharSet::new(vec![('0', '9'), ('_', '_')]));
+        let set = CharSet::new(vec![('\0', '\u{D7FF}')]).negate();
+        assert_eq!(set.ranges(), &[('\u{E000}', std::char::MAX)]);
+        assert_eq!(CharSet::new(vec![]).negate().ranges(), &[('\0', std::char::MAX)]);
+    }
+
+    #[test]
+    fn ascii() {
+        let set = CharSet::new(vec![('\0', 'b'), ('é', 'é')]).ascii();
+        assert_eq!(set.ranges(), &[(' ', 'b')]);
+    }
+
+    #[test]
+    fn sample() {
+        let set = CharSet::new(vec![('a', 'b'), ('é', 'é')]);
+        let mut rng = thread_rng();
+        for _ in 0..20 {
+            assert!(set.contains(set.sample(&mut rng)));
+        }
+    }
+}
@@ -7,7 +7,6 @@ use super::State::*;
  * Both at the internal representation level and in dot format
  * to generate a graphical representation.
  */
-
 /**
  * Generate a string of the internal structure of the NFA.
  */
@@ -32,6 +31,28 @@ pub fn nfa_dot(nfa: &NFA) -> String {
                 "\t{0} -> {1} [label=\"ε\"]\n\t{0} -> {2} [label=\"ε\"]\n",
                 id, rhs, lhs
             ),
+            Assert(assertion, Some(next)) => format!(
+                "\t{} -> {} [label=\"ε {}\"]\n",
+                id,
+                next,
+                assertion.to_string().replace('\\', "\\\\")
+            ),
+            Save(slot, Some(next)) => format!(
+                "\t{} -> {} [label=\"ε {}{}\"]\n",
+                id,
+                next,
+                if slot % 2 == 0 { '(' } else { ')' },
+                slot / 2
+            ),
+            Backref(group, _, Some(next)) => format!("\t{} -> {} [label=\"\\\\{}\"]\n", id, next, group),
+            LookAround(look, body, _, Some(next)) => format!(
+                "\t{0} -> {1} [label=\"ε {3}\"]\n\t{0} -> {2} [style=\"dashed\"]\n",
+                id,
+                next,
+                body,
+                crate::tokenizer::Token::Look(*look)
+            ),
+            LookEnd => format!("\t{} [shape=\"doublecircle\" style=\"dashed\"]\n", id),
             End => format!("\t{} [shape=\"doublecircle\"]\n", id),
             _ => String::new(),
         });
@@ -47,7 +68,18 @@ impl std::fmt::Display for Char {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Char::Literal(c) => write!(f, "{}", c),
-            Char::Any => write!(f, "ANY"),
+            Char::Any(_) => write!(f, "ANY"),
+            Char::Set(set, _) => {
+                write!(f, "[")?;
+                for &(lo, hi) in set.ranges() {
+                    if lo == hi {
+                        write!(f, "{}", lo.escape_default())?;
+                    } else {
+                        write!(f, "{}-{}", lo.escape_default(), hi.escape_default())?;
+                    }
+                }
+                write!(f, "]")
+            }
         }
     }
 }
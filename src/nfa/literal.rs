@@ -0,0 +1,248 @@
+use super::{literal, Char};
+use crate::parser::{Flags, AST};
+
+/**
+ * What is known about the literal text in the matches of part of a
+ * pattern.
+ * - exact is the only string it can match, when there is just one
+ * - prefix is text that every match starts with
+ * - suffix is text that every match ends with
+ * - required is text that every match contains somewhere
+ *
+ * Any of them may be empty when nothing is known.
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Literals {
+    exact: Option<Vec<u8>>,
+    prefix: Vec<u8>,
+    suffix: Vec<u8>,
+    required: Vec<u8>,
+}
+
+impl Literals {
+    fn exact(bytes: Vec<u8>) -> Literals {
+        Literals {
+            exact: Some(bytes.clone()),
+            prefix: bytes.clone(),
+            suffix: bytes.clone(),
+            required: bytes,
+        }
+    }
+}
+
+/**
+ * The longest text that every match of any of asts, matched with flags,
+ * contains, if there is any, which can be searched for to rule out input
+ * before the automaton is run on it.
+ */
+pub fn required<'a>(asts: impl IntoIterator<Item = &'a AST>, flags: Flags) -> Option<Vec<u8>> {
+    let required = asts
+        .into_iter()
+        .map(|ast| literals(ast, flags))
+        .reduce(either)?
+        .required;
+    if required.is_empty() {
+        None
+    } else {
+        Some(required)
+    }
+}
+
+fn literals(ast: &AST, flags: Flags) -> Literals {
+    match ast {
+        AST::Char(c) => match literal(*c, flags) {
+            // a char that case folding leaves alone is still a literal
+            Char::Literal(c) => Literals::exact(c.to_string().into_bytes()),
+            _ => Literals::default(),
+        },
+        AST::AnyChar
+        | AST::Class(_)
+        | AST::Bracket(_)
+        | AST::Closure(..)
+        | AST::Optional(..)
+        | AST::Backref(_) => Literals::default(),
+        // assertions match the empty string, so they leave the literals around them joined up
+        AST::Assertion(_) | AST::Look(..) => Literals::exact(vec![]),
+        AST::OneOrMore(e, _) => Literals {
+            exact: None,
+            ..literals(e, flags)
+        },
+        AST::Catenation(lhs, rhs) => {
+            let (lhs, rhs) = (literals(lhs, flags), literals(rhs, flags));
+            let exact = match (&lhs.exact, &rhs.exact) {
+                (Some(l), Some(r)) => Some([&l[..], &r[..]].concat()),
+                _ => None,
+            };
+            let prefix = match &lhs.exact {
+                Some(l) => [&l[..], &rhs.prefix[..]].concat(),
+                None => lhs.prefix,
+            };
+            let suffix = match &rhs.exact {
+                Some(r) => [&lhs.suffix[..], &r[..]].concat(),
+                None => rhs.suffix,
+            };
+            //where the two meet, the end of one runs straight into the start of the other
+            let joined = [&lhs.suffix[..], &rhs.prefix[..]].concat();
+            let required = longest(vec![
+                lhs.required,
+                rhs.required,
+                joined,
+                prefix.clone(),
+                suffix.clone(),
+            ]);
+            Literals {
+                exact,
+                prefix,
+                suffix,
+                required,
+            }
+        }
+        AST::Alternation(lhs, rhs) => either(literals(lhs, flags), literals(rhs, flags)),
+        AST::Flags(flags, e) => literals(e, *flags),
+        AST::Group(_, _, e) => literals(e, flags),
+    }
+}
+
+/**
+ * What is known about the matches of either of two parts of a pattern.
+ */
+fn either(lhs: Literals, rhs: Literals) -> Literals {
+    if lhs.exact.is_some() && lhs.exact == rhs.exact {
+        return lhs;
+    }
+    let prefix = common(lhs.prefix.iter(), rhs.prefix.iter());
+    let mut suffix = common(lhs.suffix.iter().rev(), rhs.suffix.iter().rev());
+    suffix.reverse();
+    let required = longest(vec![prefix.clone(), suffix.clone()]);
+    Literals {
+        exact: None,
+        prefix,
+        suffix,
+        required,
+    }
+}
+
+fn longest(candidates: Vec<Vec<u8>>) -> Vec<u8> {
+    candidates
+        .into_iter()
+        .fold(vec![], |best, c| if c.len() > best.len() { c } else { best })
+}
+
+/**
+ * The bytes two sequences start with in common.
+ */
+fn common<'a>(lhs: impl Iterator<Item = &'a u8>, rhs: impl Iterator<Item = &'a u8>) -> Vec<u8> {
+    lhs.zip(rhs).take_while(|(l, r)| l == r).map(|(l, _)| *l).collect()
+}
+
+/**
+ * A Finder searches for a literal with the Boyer-Moore-Horspool
+ * algorithm, which compares the last byte of the window first and skips
+ * ahead by as much as the whole literal when that byte is not in it.
+ */
+#[derive(Debug, Clone)]
+pub struct Finder {
+    needle: Vec<u8>,
+    shift: [usize; 256],
+}
+
+impl Finder {
+    pub fn new(needle: Vec<u8>) -> Finder {
+        let mut shift = [needle.len(); 256];
+        for (i, byte) in needle.iter().enumerate().take(needle.len().saturating_sub(1)) {
+            shift[*byte as usize] = needle.len() - 1 - i;
+        }
+        Finder { needle, shift }
+    }
+
+    pub fn needle(&self) -> &[u8] {
+        &self.needle
+    }
+
+    /**
+     * The offset of the first occurrence of the literal in haystack.
+     */
+    pub fn find(&self, haystack: &[u8]) -> Option<usize> {
+        let n = self.needle.len();
+        if n == 0 {
+            return Some(0);
+        }
+        let last = self.needle[n - 1];
+        let mut at = 0;
+        while at + n <= haystack.len() {
+            let byte = haystack[at + n - 1];
+            if byte == last && haystack[at..at + n - 1] == self.needle[..n - 1] {
+                return Some(at);
+            }
+            at += self.shift[byte as usize];
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::tokenizer::Tokenizer;
+
+    fn required_in(pattern: &str) -> String {
+        let ast = Parser::parse(Tokenizer::new(pattern)).unwrap();
+        String::from_utf8(required(&[ast], Flags::default()).unwrap_or_default()).unwrap()
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(required_in("ERROR"), "ERROR");
+        assert_eq!(required_in("timeout=.*"), "timeout=");
+        assert_eq!(required_in(".*timeout=(1|2)+s"), "timeout=");
+        assert_eq!(required_in("a*b"), "b");
+        assert_eq!(required_in("(ab)+"), "ab");
+        assert_eq!(required_in("é+x"), "éx");
+        assert_eq!(required_in("\\bfoo\\b"), "foo");
+    }
+
+    #[test]
+    fn factors() {
+        assert_eq!(required_in("(a|b)cde(f|g)"), "cde");
+        assert_eq!(required_in("x.*needle.*y"), "needle");
+        assert_eq!(required_in("err(or)*"), "err");
+    }
+
+    #[test]
+    fn alternation() {
+        assert_eq!(required_in("foobar|foobaz"), "fooba");
+        assert_eq!(required_in("abcxyz|zzxyz"), "xyz");
+        assert_eq!(required_in("abc|abc"), "abc");
+        assert_eq!(required_in("cat|dog"), "");
+    }
+
+    #[test]
+    fn patterns() {
+        let asts: Vec<_> = ["timeout=1", "timeout=.*s"]
+            .iter()
+            .map(|p| Parser::parse(Tokenizer::new(p)).unwrap())
+            .collect();
+        assert_eq!(required(&asts, Flags::default()), Some(b"timeout=".to_vec()));
+        assert_eq!(required(&[], Flags::default()), None);
+    }
+
+    #[test]
+    fn nothing_required() {
+        assert_eq!(required_in("a*"), "");
+        assert_eq!(required_in("."), "");
+        assert_eq!(required_in("(?i)ERROR"), "");
+        assert_eq!(required_in("(?i)e-1"), "-1");
+    }
+
+    #[test]
+    fn finder() {
+        let finder = Finder::new(b"needle".to_vec());
+        assert_eq!(finder.find(b"haystack with a needle in it"), Some(16));
+        assert_eq!(finder.find(b"needle"), Some(0));
+        assert_eq!(finder.find(b"needl"), None);
+        assert_eq!(finder.find(b"neeneedle"), Some(3));
+        assert_eq!(Finder::new(b"x".to_vec()).find(b"abcx"), Some(3));
+        assert_eq!(Finder::new(b"aa".to_vec()).find(b"baaa"), Some(1));
+    }
+}
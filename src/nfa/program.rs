@@ -0,0 +1,597 @@
+use super::utf8;
+use super::{State, NFA};
+use crate::matcher::{word_after, word_before};
+use crate::tokenizer::Assertion;
+
+/**
+ * A Program is the byte-level automaton an NFA is compiled into for
+ * searching. Every Char edge of the NFA becomes a small trie of byte
+ * range transitions spelling out the UTF-8 encodings of the chars it
+ * matches, so input can be searched as raw bytes without first being
+ * validated or decoded. A byte that is not part of a valid encoding
+ * has no transition in any trie, but AnyChar and negated classes have a
+ * fallback for it, which matches it on its own as the decoder once did.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    start: InstId,
+    insts: Vec<Inst>,
+}
+
+type InstId = usize;
+
+/**
+ * Instructions are the states of a Program.
+ * - Bytes is a state with a transition for each range of bytes it takes,
+ *   and maybe a fallback taken on a byte that does not begin a valid
+ *   encoding
+ * - Jump is a state with a single epsilon transition out
+ * - Save is a Jump that records the offset it is taken at in a slot, for
+ *   the start or end of a group; only captures looks at the slot
+ * - Split is a state with two epsilon transitions out
+ * - Assert is a state with an epsilon transition out taken only where
+ *   its Assertion holds of the input
+ * - Match is the accepting state
+ */
+#[derive(Debug, Clone)]
+enum Inst {
+    Bytes(Vec<Transition>, Option<InstId>),
+    Jump(InstId),
+    Save(usize, InstId),
+    Split(InstId, InstId),
+    Assert(Assertion, InstId),
+    Match,
+}
+
+#[derive(Debug, Clone)]
+struct Transition {
+    lo: u8,
+    hi: u8,
+    next: InstId,
+}
+
+impl Program {
+    /**
+     * Compile the states of an NFA into a Program. Instructions
+     * 0..states.len() correspond one to one with the NFA's states, and
+     * the inner nodes of each Char's byte trie are added after them.
+     */
+    pub fn new(nfa: &NFA) -> Program {
+        let mut program = Program {
+            start: nfa.start,
+            insts: Vec::with_capacity(nfa.states.len()),
+        };
+        for state in &nfa.states {
+            program.insts.push(match state {
+                State::Start(Some(next)) => Inst::Jump(*next),
+                State::Split(Some(lhs), Some(rhs)) => Inst::Split(*lhs, *rhs),
+                State::Assert(assertion, Some(next)) => Inst::Assert(*assertion, *next),
+                State::Save(slot, Some(next)) => Inst::Save(*slot, *next),
+                // a Program cannot tell what a group matched or look around, so backreferences
+                // and lookaround are dead ends, and NFAs with them in are searched by a Backtrack
+                // matcher instead
+                State::Backref(..) | State::LookAround(..) | State::LookEnd => Inst::Bytes(vec![], None),
+                State::End => Inst::Match,
+                // Match states are filled in below, once there is room for their tries
+                _ => Inst::Match,
+            });
+        }
+        for (id, state) in nfa.states.iter().enumerate() {
+            if let State::Match(c, Some(next)) = state {
+                let mut trie = Trie::default();
+                for (lo, hi) in c.ranges() {
+                    for seq in utf8::sequences(lo, hi) {
+                        trie.insert(&seq);
+                    }
+                }
+                let invalid = if c.matches_invalid() { Some(*next) } else { None };
+                program.insts[id] = Inst::Bytes(program.emit(&trie, *next), invalid);
+            }
+        }
+        program
+    }
+
+    /**
+     * Whether the whole of input is matched by the Program.
+     */
+    pub fn accepts(&self, input: &[u8]) -> bool {
+        self.run(input, true)
+    }
+
+    /**
+     * Whether the Program matches anywhere within haystack.
+     */
+    pub fn is_match(&self, haystack: &[u8]) -> bool {
+        self.run(haystack, false)
+    }
+
+    /**
+     * Find the leftmost-longest match within haystack that starts at or
+     * after the offset at, returning the byte offsets of its start and end.
+     */
+    pub fn find_at(&self, haystack: &[u8], at: usize) -> Option<(usize, usize)> {
+        let mut curr = StateSet::new(self.insts.len());
+        let mut next = StateSet::new(self.insts.len());
+        let mut found: Option<(usize, usize)> = None;
+        for pos in at..=haystack.len() {
+            // attempts stop beginning once there is a match, since any they
+            // found would start further right
+            if found.is_none() {
+                self.add(&mut curr, self.start, pos, haystack, pos);
+            }
+            for id in curr.iter() {
+                let start = curr.start(id);
+                if self.is_match_inst(id) && found.is_none_or(|(s, _)| start <= s) {
+                    found = Some((start, pos));
+                }
+            }
+            if pos == haystack.len() || (found.is_some() && curr.is_empty()) {
+                break;
+            }
+            next.clear();
+            for id in curr.iter() {
+                let start = curr.start(id);
+                // attempts that began right of a match can never beat it
+                if found.is_some_and(|(s, _)| start > s) {
+                    continue;
+                }
+                self.step(id, haystack, pos, |to| self.add(&mut next, to, start, haystack, pos + 1));
+            }
+            std::mem::swap(&mut curr, &mut next);
+        }
+        found
+    }
+
+    /**
+     * Find the leftmost-first match within haystack that starts at or
+     * after the offset at. This is a Pike VM: the attempts in a set are in
+     * order of priority, the order a backtracking search would try them
+     * in, since add follows the first leg of a Split first and attempts
+     * that began earlier come before those that began later. Once an
+     * attempt matches, every attempt after it is dropped, since any match
+     * they went on to find would lose to it, while those before it carry
+     * on and replace it with any match they find.
+     */
+    pub fn find_first_at(&self, haystack: &[u8], at: usize) -> Option<(usize, usize)> {
+        let mut curr = StateSet::new(self.insts.len());
+        let mut next = StateSet::new(self.insts.len());
+        let mut found: Option<(usize, usize)> = None;
+        for pos in at..=haystack.len() {
+            if found.is_none() {
+                self.add(&mut curr, self.start, pos, haystack, pos);
+            }
+            next.clear();
+            for id in curr.iter() {
+                let start = curr.start(id);
+                match &self.insts[id] {
+                    Inst::Match => {
+                        found = Some((start, pos));
+                        break;
+                    }
+                    Inst::Bytes(..) if pos < haystack.len() => {
+                        self.step(id, haystack, pos, |to| self.add(&mut next, to, start, haystack, pos + 1));
+                    }
+                    _ => {}
+                }
+            }
+            if pos == haystack.len() || (found.is_some() && next.is_empty()) {
+                break;
+            }
+            std::mem::swap(&mut curr, &mut next);
+        }
+        found
+    }
+
+    /**
+     * Where each group matched on the path through the Program that
+     * matches haystack from start to end, as slots offsets of the starts
+     * and ends of groups, or None if there is no such path. Where there
+     * are several, the one with the highest priority is taken, as in
+     * find_first_at, which makes it the path of the leftmost-first match
+     * when end is where that ends. Each attempt carries the slots for its
+     * path with it, so this costs more than finding the match did.
+     */
+    pub fn captures(&self, haystack: &[u8], start: usize, end: usize, slots: usize) -> Option<Vec<Option<usize>>> {
+        let mut curr = StateSet::new(self.insts.len());
+        let mut next = StateSet::new(self.insts.len());
+        let mut curr_slots = vec![vec![]; self.insts.len()];
+        let mut next_slots = vec![vec![]; self.insts.len()];
+        let empty = vec![None; slots];
+        self.add_saving(&mut curr, &mut curr_slots, self.start, empty, haystack, start);
+        for pos in start..=end {
+            next.clear();
+            for id in curr.iter() {
+                match &self.insts[id] {
+                    Inst::Match if pos == end => return Some(std::mem::take(&mut curr_slots[id])),
+                    Inst::Bytes(..) if pos < end => {
+                        self.step(id, haystack, pos, |to| {
+                            let saved = curr_slots[id].clone();
+                            self.add_saving(&mut next, &mut next_slots, to, saved, haystack, pos + 1);
+                        });
+                    }
+                    _ => {}
+                }
+            }
+            std::mem::swap(&mut curr, &mut next);
+            std::mem::swap(&mut curr_slots, &mut next_slots);
+        }
+        None
+    }
+
+    /**
+     * The Match instructions reached anywhere within haystack, each
+     * once, in the order they were first reached.
+     */
+    pub fn matches(&self, haystack: &[u8]) -> Vec<InstId> {
+        let mut curr = StateSet::new(self.insts.len());
+        let mut next = StateSet::new(self.insts.len());
+        let mut matched = vec![];
+        for pos in 0..=haystack.len() {
+            self.add(&mut curr, self.start, pos, haystack, pos);
+            for id in curr.iter() {
+                if self.is_match_inst(id) && !matched.contains(&id) {
+                    matched.push(id);
+                }
+            }
+            if pos == haystack.len() {
+                break;
+            }
+            next.clear();
+            for id in curr.iter() {
+                self.step(id, haystack, pos, |to| self.add(&mut next, to, pos + 1, haystack, pos + 1));
+            }
+            std::mem::swap(&mut curr, &mut next);
+        }
+        matched
+    }
+
+    /**
+     * The Match instructions reached by attempts that begin at start,
+     * each with the offset the match it completes ends at, in order of
+     * those offsets.
+     */
+    pub fn matches_at(&self, haystack: &[u8], start: usize) -> Vec<(usize, InstId)> {
+        let mut curr = StateSet::new(self.insts.len());
+        let mut next = StateSet::new(self.insts.len());
+        let mut matched = vec![];
+        self.add(&mut curr, self.start, start, haystack, start);
+        for pos in start..=haystack.len() {
+            for id in curr.iter() {
+                if self.is_match_inst(id) {
+                    matched.push((pos, id));
+                }
+            }
+            if pos == haystack.len() || curr.is_empty() {
+                break;
+            }
+            next.clear();
+            for id in curr.iter() {
+                self.step(id, haystack, pos, |to| self.add(&mut next, to, start, haystack, pos + 1));
+            }
+            std::mem::swap(&mut curr, &mut next);
+        }
+        matched
+    }
+
+    /**
+     * Call f with each instruction that the Bytes instruction id moves on
+     * to over the byte of haystack at pos. The fallback is only taken when
+     * that byte is a unit of its own, one that does not begin a valid
+     * encoding.
+     */
+    fn step(&self, id: InstId, haystack: &[u8], pos: usize, mut f: impl FnMut(InstId)) {
+        if let Inst::Bytes(transitions, invalid) = &self.insts[id] {
+            let byte = haystack[pos];
+            for t in transitions {
+                if t.lo <= byte && byte <= t.hi {
+                    f(t.next);
+                }
+            }
+            if let Some(next) = invalid {
+                if byte >= 0x80 && utf8::decode(&haystack[pos..]) == Some((None, 1)) {
+                    f(*next);
+                }
+            }
+        }
+    }
+
+    /**
+     * Add the instructions for a trie of byte ranges, returning the
+     * transitions out of its root. Leaves of the trie lead to next.
+     */
+    fn emit(&mut self, trie: &Trie, next: InstId) -> Vec<Transition> {
+        let mut transitions = vec![];
+        for (lo, hi, child) in &trie.edges {
+            let target = if child.edges.is_empty() {
+                next
+            } else {
+                let inner = self.emit(child, next);
+                self.insts.push(Inst::Bytes(inner, None));
+                self.insts.len() - 1
+            };
+            transitions.push(Transition {
+                lo: *lo,
+                hi: *hi,
+                next: target,
+            });
+        }
+        transitions
+    }
+
+    /**
+     * Simulate the Program on all of its states at once, one byte at a
+     * time, in the style of Thompson's construction. When anchored, the
+     * match must begin at the start of input and finish at its end.
+     * Otherwise a new attempt begins at every byte and any match counts.
+     */
+    fn run(&self, input: &[u8], anchored: bool) -> bool {
+        let mut curr = StateSet::new(self.insts.len());
+        let mut next = StateSet::new(self.insts.len());
+        for pos in 0..=input.len() {
+            if pos == 0 || !anchored {
+                self.add(&mut curr, self.start, pos, input, pos);
+            }
+            if !anchored && curr.iter().any(|id| self.is_match_inst(id)) {
+                return true;
+            }
+            if pos == input.len() {
+                break;
+            }
+            next.clear();
+            for id in curr.iter() {
+                self.step(id, input, pos, |to| self.add(&mut next, to, pos + 1, input, pos + 1));
+            }
+            std::mem::swap(&mut curr, &mut next);
+            if anchored && curr.is_empty() {
+                return false;
+            }
+        }
+        let matched = curr.iter().any(|id| self.is_match_inst(id));
+        matched
+    }
+
+    /**
+     * Add a state to the set along with every state reachable from it
+     * by epsilon transitions, all remembering start as the position the
+     * attempt that reached them began at. Attempts are added in the
+     * order they began, so a state reached by several keeps the earliest.
+     * Assertions are checked against haystack at the offset at, which is
+     * where in it the states are being added.
+     */
+    fn add(&self, set: &mut StateSet, id: InstId, start: usize, haystack: &[u8], at: usize) {
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if set.contains(id) {
+                continue;
+            }
+            set.insert(id, start);
+            match self.insts[id] {
+                Inst::Jump(next) | Inst::Save(_, next) => stack.push(next),
+                // the first leg is pushed last so that it is followed first
+                Inst::Split(lhs, rhs) => {
+                    stack.push(rhs);
+                    stack.push(lhs);
+                }
+                Inst::Assert(assertion, next) if holds(assertion, haystack, at) => stack.push(next),
+                Inst::Assert(..) | Inst::Bytes(..) | Inst::Match => {}
+            }
+        }
+    }
+
+    /**
+     * Add states to the set as add does, for captures. The slots of the
+     * path that reached each Bytes or Match state first are kept in
+     * slots, with the offset at recorded in those a Save on it is for.
+     */
+    fn add_saving(
+        &self,
+        set: &mut StateSet,
+        slots: &mut [Vec<Option<usize>>],
+        id: InstId,
+        saved: Vec<Option<usize>>,
+        haystack: &[u8],
+        at: usize,
+    ) {
+        let mut stack = vec![(id, saved)];
+        while let Some((id, mut saved)) = stack.pop() {
+            if set.contains(id) {
+                continue;
+            }
+            set.insert(id, at);
+            match self.insts[id] {
+                Inst::Jump(next) => stack.push((next, saved)),
+                Inst::Save(slot, next) => {
+                    saved[slot] = Some(at);
+                    stack.push((next, saved));
+                }
+                Inst::Split(lhs, rhs) => {
+                    stack.push((rhs, saved.clone()));
+                    stack.push((lhs, saved));
+                }
+                Inst::Assert(assertion, next) if holds(assertion, haystack, at) => stack.push((next, saved)),
+                Inst::Assert(..) => {}
+                Inst::Bytes(..) | Inst::Match => slots[id] = saved,
+            }
+        }
+    }
+
+    fn is_match_inst(&self, id: InstId) -> bool {
+        matches!(self.insts[id], Inst::Match)
+    }
+}
+
+/**
+ * Whether an assertion holds at the offset at in haystack.
+ */
+pub fn holds(assertion: Assertion, haystack: &[u8], at: usize) -> bool {
+    let boundary = word_before(haystack, at) != word_after(haystack, at);
+    match assertion {
+        Assertion::WordBoundary => boundary,
+        Assertion::NotWordBoundary => !boundary,
+    }
+}
+
+/**
+ * A trie of byte range sequences used while compiling a Char.
+ */
+#[derive(Debug, Default)]
+struct Trie {
+    edges: Vec<(u8, u8, Trie)>,
+}
+
+impl Trie {
+    fn insert(&mut self, seq: &[(u8, u8)]) {
+        let (lo, hi) = seq[0];
+        let rest = &seq[1..];
+        if !rest.is_empty() {
+            for (l, h, child) in &mut self.edges {
+                if *l == lo && *h == hi && !child.edges.is_empty() {
+                    child.insert(rest);
+                    return;
+                }
+            }
+        }
+        let mut child = Trie::default();
+        if !rest.is_empty() {
+            child.insert(rest);
+        }
+        self.edges.push((lo, hi, child));
+    }
+}
+
+/**
+ * A set of instruction ids that remembers the order they were added in
+ * and can be cleared in constant time, as described in Briggs and
+ * Torczon's "An Efficient Representation for Sparse Sets". Each id also
+ * carries the position its attempt at a match began at.
+ */
+struct StateSet {
+    dense: Vec<InstId>,
+    sparse: Vec<usize>,
+    starts: Vec<usize>,
+}
+
+impl StateSet {
+    fn new(size: usize) -> StateSet {
+        StateSet {
+            dense: Vec::with_capacity(size),
+            sparse: vec![0; size],
+            starts: vec![0; size],
+        }
+    }
+
+    fn contains(&self, id: InstId) -> bool {
+        let i = self.sparse[id];
+        i < self.dense.len() && self.dense[i] == id
+    }
+
+    fn insert(&mut self, id: InstId, start: usize) {
+        self.sparse[id] = self.dense.len();
+        self.dense.push(id);
+        self.starts[id] = start;
+    }
+
+    fn start(&self, id: InstId) -> usize {
+        self.starts[id]
+    }
+
+    fn clear(&mut self) {
+        self.dense.clear();
+    }
+
+    fn is_empty(&self) -> bool {
+        self.dense.is_empty()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = InstId> + '_ {
+        self.dense.iter().cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_of_literals() {
+        let nfa = NFA::from("é").unwrap();
+        assert!(nfa.program.accepts("é".as_bytes()));
+        assert!(!nfa.program.accepts(b"\xc3"));
+        assert!(!nfa.program.accepts(b"\xe9"));
+    }
+
+    #[test]
+    fn any_is_one_char() {
+        let nfa = NFA::from("a.b").unwrap();
+        assert!(nfa.program.accepts(b"axb"));
+        assert!(nfa.program.accepts("aéb".as_bytes()));
+        assert!(nfa.program.accepts("a😀b".as_bytes()));
+        assert!(nfa.program.accepts(b"a\xffb"));
+        //an encoded surrogate is not a char, so it is three bytes matched one at a time
+        assert!(!nfa.program.accepts(b"a\xed\xa0\x80b"));
+        let nfa = NFA::from("a..b").unwrap();
+        assert!(!nfa.program.accepts("aéb".as_bytes()));
+        assert!(NFA::from("a...b").unwrap().program.accepts(b"a\xed\xa0\x80b"));
+    }
+
+    #[test]
+    fn unanchored() {
+        let nfa = NFA::from("err(or)+").unwrap();
+        assert!(nfa.program.is_match(b"caf\xe9: error!"));
+        assert!(nfa.program.is_match(b"erroror"));
+        assert!(!nfa.program.is_match(b"erro"));
+        assert!(!nfa.program.accepts(b" error"));
+    }
+
+    #[test]
+    fn leftmost_longest() {
+        let nfa = NFA::from("a|ab|abc").unwrap();
+        assert_eq!(nfa.program.find_at(b"xabcd", 0), Some((1, 4)));
+        let nfa = NFA::from("b*c|abx").unwrap();
+        assert_eq!(nfa.program.find_at(b"abbc", 0), Some((1, 4)));
+        let nfa = NFA::from("é+").unwrap();
+        assert_eq!(nfa.program.find_at("caféé!".as_bytes(), 0), Some((3, 7)));
+        assert_eq!(nfa.program.find_at(b"cafe", 0), None);
+        assert_eq!(nfa.program.find_at("caféé!".as_bytes(), 5), Some((5, 7)));
+    }
+
+    #[test]
+    fn leftmost_first() {
+        let nfa = NFA::from("\"a.*?\"").unwrap();
+        assert_eq!(nfa.program.find_first_at(b"x \"ab\" \"c\"", 0), Some((2, 6)));
+        let nfa = NFA::from("a|ab|abc").unwrap();
+        assert_eq!(nfa.program.find_first_at(b"xabcd", 0), Some((1, 2)));
+        let nfa = NFA::from("abc|ab|a").unwrap();
+        assert_eq!(nfa.program.find_first_at(b"xabd", 0), Some((1, 3)));
+        let nfa = NFA::from("a+?").unwrap();
+        assert_eq!(nfa.program.find_first_at(b"baaa", 0), Some((1, 2)));
+        let nfa = NFA::from("a*?b").unwrap();
+        assert_eq!(nfa.program.find_first_at(b"caab", 0), Some((1, 4)));
+        let nfa = NFA::from("ab??").unwrap();
+        assert_eq!(nfa.program.find_first_at(b"ab", 0), Some((0, 1)));
+        let nfa = NFA::from("(a*?)*?\\b").unwrap();
+        assert_eq!(nfa.program.find_first_at(b"aa", 1), Some((1, 2)));
+    }
+
+    #[test]
+    fn captures() {
+        let nfa = NFA::from("(a+)(b)?c").unwrap();
+        let captures = nfa.program.captures(b"xaac", 1, 4, 6);
+        assert_eq!(captures, Some(vec![None, None, Some(1), Some(3), None, None]));
+        let nfa = NFA::from("(a|ab)(c|bcd)").unwrap();
+        let captures = nfa.program.captures(b"abcd", 0, 4, 6).unwrap();
+        assert_eq!(captures[2..], [Some(0), Some(1), Some(1), Some(4)]);
+        let nfa = NFA::from("(a*)+").unwrap();
+        assert_eq!(nfa.program.captures(b"aa", 0, 2, 4).unwrap()[2..], [Some(0), Some(2)]);
+        assert_eq!(nfa.program.captures(b"aa", 0, 1, 4).unwrap()[2..], [Some(0), Some(1)]);
+        assert_eq!(nfa.program.captures(b"ab", 0, 2, 4), None);
+    }
+
+    #[test]
+    fn empty_loops() {
+        let nfa = NFA::from("(a*)*b").unwrap();
+        assert!(nfa.program.accepts(b"aab"));
+        assert!(!nfa.program.accepts(b"aac"));
+        assert!(nfa.program.is_match(b"cccb"));
+    }
+}
@@ -0,0 +1,77 @@
+/*
+ * Tables of Unicode character data embedded in thegrep. They are kept
+ * in their own files since they are long and generated rather than
+ * written by hand.
+ */
+mod case_folding;
+mod general_category;
+mod script;
+
+pub use self::case_folding::CASE_FOLDING_SIMPLE;
+pub use self::general_category::{GENERAL_CATEGORY, GENERAL_CATEGORY_ALIASES};
+pub use self::script::{SCRIPT, SCRIPT_ALIASES};
+
+/**
+ * The name and ranges of the general category or script called name, like
+ * L, Letter, Nd, Greek or Grek. Names are matched loosely, ignoring case,
+ * spaces, '_' and '-', and a name can say which kind it is with gc= or
+ * sc= (or General_Category= and Script=) in front of it. A name that is
+ * both is taken as the general category.
+ */
+pub fn property(name: &str) -> Option<(&'static str, &'static [(char, char)])> {
+    let (kind, name) = match name.split_once('=') {
+        Some((kind, name)) => (Some(loose(kind)), name),
+        None => (None, name),
+    };
+    let name = loose(name);
+    let general_category = || lookup(&name, GENERAL_CATEGORY, GENERAL_CATEGORY_ALIASES);
+    let script = || lookup(&name, SCRIPT, SCRIPT_ALIASES);
+    match kind.as_deref() {
+        None => general_category().or_else(script),
+        Some("gc") | Some("generalcategory") => general_category(),
+        Some("sc") | Some("script") => script(),
+        Some(_) => None,
+    }
+}
+
+fn lookup(
+    name: &str,
+    table: &'static [(&'static str, &'static [(char, char)])],
+    aliases: &'static [(&'static str, &'static str)],
+) -> Option<(&'static str, &'static [(char, char)])> {
+    let name = aliases.iter().find(|(alias, _)| loose(alias) == name).map_or(name, |(_, n)| *n);
+    table.iter().find(|(n, _)| loose(n) == loose(name)).copied()
+}
+
+fn loose(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, ' ' | '_' | '-'))
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn properties() {
+        assert_eq!(property("L").map(|(n, _)| n), Some("L"));
+        assert_eq!(property("lowercase letter").map(|(n, _)| n), Some("Ll"));
+        assert_eq!(property("Grek").map(|(n, _)| n), Some("Greek"));
+        assert_eq!(property("sc=greek").map(|(n, _)| n), Some("Greek"));
+        assert_eq!(property("Script=Latin").map(|(n, _)| n), Some("Latin"));
+        assert_eq!(property("gc=Greek"), None);
+        assert_eq!(property("Klingon"), None);
+        let (_, nd) = property("Nd").unwrap();
+        assert_eq!(nd[0], ('0', '9'));
+    }
+
+    #[test]
+    fn sorted() {
+        for (_, ranges) in GENERAL_CATEGORY.iter().chain(SCRIPT) {
+            assert!(ranges.iter().all(|(lo, hi)| lo <= hi));
+            assert!(ranges.windows(2).all(|w| w[0].1 < w[1].0));
+        }
+    }
+}
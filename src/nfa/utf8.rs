@@ -0,0 +1,177 @@
+/*
+ * Helpers for walking over input bytes that are expected, but not
+ * guaranteed, to be UTF-8.
+ */
+
+/**
+ * Decode the first unit of input. A unit is either a whole UTF-8
+ * encoded char, or a single byte that does not begin a valid encoding,
+ * which is decoded as None. Returns the unit along with how many bytes
+ * it took up, or None when the input is empty.
+ */
+pub fn decode(input: &[u8]) -> Option<(Option<char>, usize)> {
+    let first = *input.first()?;
+    if first < 0x80 {
+        return Some((Some(first as char), 1));
+    }
+    let len = match first {
+        0xC2..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF4 => 4,
+        _ => return Some((None, 1)),
+    };
+    if input.len() < len {
+        return Some((None, 1));
+    }
+    match std::str::from_utf8(&input[..len]) {
+        Ok(s) => Some((s.chars().next(), len)),
+        Err(_) => Some((None, 1)),
+    }
+}
+
+/**
+ * Break the chars in [lo, hi] into sequences of byte ranges such that a
+ * byte string is the UTF-8 encoding of one of those chars exactly when
+ * it is matched, range by range, by one of the sequences. This is the
+ * construction RE2 uses to run Unicode-aware automata over bytes.
+ */
+pub fn sequences(lo: char, hi: char) -> Vec<Vec<(u8, u8)>> {
+    let mut seqs = vec![];
+    push_sequences(lo as u32, hi as u32, &mut seqs);
+    seqs
+}
+
+fn push_sequences(lo: u32, hi: u32, seqs: &mut Vec<Vec<(u8, u8)>>) {
+    if lo > hi {
+        return;
+    }
+    // surrogates are not chars and have no encoding, so they are cut out of the range
+    if lo < 0xD800 && hi > 0xDFFF {
+        push_sequences(lo, 0xD7FF, seqs);
+        push_sequences(0xE000, hi, seqs);
+        return;
+    }
+    let lo = if (0xD800..=0xDFFF).contains(&lo) { 0xE000 } else { lo };
+    let hi = if (0xD800..=0xDFFF).contains(&hi) { 0xD7FF } else { hi };
+    if lo > hi {
+        return;
+    }
+    // split where the encoded length changes so both ends encode to the same length
+    for &max in &[0x7F, 0x7FF, 0xFFFF] {
+        if lo <= max && max < hi {
+            push_sequences(lo, max, seqs);
+            push_sequences(max + 1, hi, seqs);
+            return;
+        }
+    }
+    if hi <= 0x7F {
+        seqs.push(vec![(lo as u8, hi as u8)]);
+        return;
+    }
+    // split until every continuation byte after the first differing one spans 80-BF
+    for i in 1..4 {
+        let m = (1 << (6 * i)) - 1;
+        if lo & !m != hi & !m {
+            if lo & m != 0 {
+                push_sequences(lo, lo | m, seqs);
+                push_sequences((lo | m) + 1, hi, seqs);
+                return;
+            }
+            if hi & m != m {
+                push_sequences(lo, (hi & !m) - 1, seqs);
+                push_sequences(hi & !m, hi, seqs);
+                return;
+            }
+        }
+    }
+    let (mut lo_buf, mut hi_buf) = ([0; 4], [0; 4]);
+    let lo_bytes = encode(lo, &mut lo_buf);
+    let hi_bytes = encode(hi, &mut hi_buf);
+    seqs.push(lo_bytes.iter().cloned().zip(hi_bytes.iter().cloned()).collect());
+}
+
+fn encode(c: u32, buf: &mut [u8; 4]) -> &[u8] {
+    std::char::from_u32(c)
+        .expect("surrogates are cut out before encoding")
+        .encode_utf8(buf)
+        .as_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii() {
+        assert_eq!(decode(b"ab"), Some((Some('a'), 1)));
+        assert_eq!(decode(b""), None);
+    }
+
+    #[test]
+    fn multibyte() {
+        assert_eq!(decode("é!".as_bytes()), Some((Some('é'), 2)));
+        assert_eq!(decode("€".as_bytes()), Some((Some('€'), 3)));
+        assert_eq!(decode("😀".as_bytes()), Some((Some('😀'), 4)));
+    }
+
+    #[test]
+    fn ascii_sequences() {
+        assert_eq!(sequences('a', 'z'), vec![vec![(b'a', b'z')]]);
+        assert_eq!(sequences('\0', '\u{7F}'), vec![vec![(0x00, 0x7F)]]);
+    }
+
+    #[test]
+    fn literal_sequences() {
+        assert_eq!(sequences('é', 'é'), vec![vec![(0xC3, 0xC3), (0xA9, 0xA9)]]);
+        assert_eq!(
+            sequences('€', '€'),
+            vec![vec![(0xE2, 0xE2), (0x82, 0x82), (0xAC, 0xAC)]]
+        );
+    }
+
+    #[test]
+    fn all_sequences() {
+        assert_eq!(
+            sequences('\0', std::char::MAX),
+            vec![
+                vec![(0x00, 0x7F)],
+                vec![(0xC2, 0xDF), (0x80, 0xBF)],
+                vec![(0xE0, 0xE0), (0xA0, 0xBF), (0x80, 0xBF)],
+                vec![(0xE1, 0xEC), (0x80, 0xBF), (0x80, 0xBF)],
+                vec![(0xED, 0xED), (0x80, 0x9F), (0x80, 0xBF)],
+                vec![(0xEE, 0xEF), (0x80, 0xBF), (0x80, 0xBF)],
+                vec![(0xF0, 0xF0), (0x90, 0xBF), (0x80, 0xBF), (0x80, 0xBF)],
+                vec![(0xF1, 0xF3), (0x80, 0xBF), (0x80, 0xBF), (0x80, 0xBF)],
+                vec![(0xF4, 0xF4), (0x80, 0x8F), (0x80, 0xBF), (0x80, 0xBF)],
+            ]
+        );
+    }
+
+    #[test]
+    fn sequences_match_encodings() {
+        // every char in a range is matched by exactly one of its sequences
+        let (lo, hi) = ('\u{370}', '\u{1100}');
+        let seqs = sequences(lo, hi);
+        for c in (0x300..0x1200).filter_map(std::char::from_u32) {
+            let mut buf = [0; 4];
+            let bytes = c.encode_utf8(&mut buf).as_bytes();
+            let hits = seqs
+                .iter()
+                .filter(|seq| {
+                    seq.len() == bytes.len()
+                        && seq.iter().zip(bytes).all(|(&(l, h), &b)| l <= b && b <= h)
+                })
+                .count();
+            assert_eq!(hits, if lo <= c && c <= hi { 1 } else { 0 });
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(decode(b"\xe9t\xe9"), Some((None, 1)));
+        assert_eq!(decode(b"\xff"), Some((None, 1)));
+        assert_eq!(decode(b"\xc3"), Some((None, 1)));
+        assert_eq!(decode(b"\xed\xa0\x80"), Some((None, 1)));
+        assert_eq!(decode(b"\x80"), Some((None, 1)));
+    }
+}
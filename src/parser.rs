@@ -1,4 +1,5 @@
-use super::tokenizer::{Token, Tokenizer};
+use super::tokenizer::{Assertion, Bracket, Class, FlagChange, Look, Span, Spanned, Token, Tokenizer};
+use std::fmt;
 use std::iter::Peekable;
 
 // Enum for Abstract Syntax Tree
@@ -6,10 +7,46 @@ use std::iter::Peekable;
 pub enum AST {
     Alternation(Box<AST>, Box<AST>),
     Catenation(Box<AST>, Box<AST>),
-    Closure(Box<AST>),
-    OneOrMore(Box<AST>),
+    Closure(Box<AST>, Greed),
+    OneOrMore(Box<AST>, Greed),
+    Optional(Box<AST>, Greed),
     Char(char),
     AnyChar,
+    Flags(Flags, Box<AST>),
+    Assertion(Assertion),
+    Class(Class),
+    Bracket(Bracket),
+    Group(usize, Option<String>, Box<AST>),
+    Backref(usize),
+    Look(Look, Box<AST>),
+}
+
+// A greedy quantifier prefers to repeat (or take) what it covers as many times as it can, and a
+// lazy one, written with a '?' after it, as few times as it can. Where a match ends only depends
+// on this when matches are leftmost-first, which is why a lazy quantifier makes them so
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Greed {
+    Greedy,
+    Lazy,
+}
+
+// Flags change how the parts of a pattern they cover match. They are set for the rest of the
+// enclosing group by inline flag groups like (?i), or inside a non-capturing group like (?i:x),
+// and a Flags node in the AST holds all of the flags in effect for the subtree under it. Extended
+// mode only changes how the pattern is tokenized, so the Tokenizer keeps track of it instead
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Flags {
+    pub case_insensitive: bool,
+    pub dot_all: bool,
+}
+
+impl Flags {
+    pub fn apply(self, change: FlagChange) -> Flags {
+        Flags {
+            case_insensitive: change.case_insensitive.unwrap_or(self.case_insensitive),
+            dot_all: change.dot_all.unwrap_or(self.dot_all),
+        }
+    }
 }
 
 // Helper functions for building AST's
@@ -22,37 +59,387 @@ pub fn catenation(lhs: AST, rhs: AST) -> AST {
 }
 
 pub fn closure(value: AST) -> AST {
-    AST::Closure(Box::new(value))
+    AST::Closure(Box::new(value), Greed::Greedy)
 }
 
 pub fn one_or_more(value: AST) -> AST {
-    AST::OneOrMore(Box::new(value))
+    AST::OneOrMore(Box::new(value), Greed::Greedy)
+}
+
+pub fn optional(value: AST) -> AST {
+    AST::Optional(Box::new(value), Greed::Greedy)
+}
+
+// Makes the quantifier at the top of value lazy, leaving anything else as it is
+pub fn lazy(value: AST) -> AST {
+    match value {
+        AST::Closure(value, _) => AST::Closure(value, Greed::Lazy),
+        AST::OneOrMore(value, _) => AST::OneOrMore(value, Greed::Lazy),
+        AST::Optional(value, _) => AST::Optional(value, Greed::Lazy),
+        value => value,
+    }
 }
 
 pub fn character(value: char) -> AST {
     AST::Char(value)
 }
 
+pub fn assertion(value: Assertion) -> AST {
+    AST::Assertion(value)
+}
+
+pub fn class(value: Class) -> AST {
+    AST::Class(value)
+}
+
+pub fn bracket(value: Bracket) -> AST {
+    AST::Bracket(value)
+}
+
+// Groups are numbered from 1 in the order their '(' comes in the pattern, named or not
+pub fn group(index: usize, value: AST) -> AST {
+    AST::Group(index, None, Box::new(value))
+}
+
+pub fn named_group(index: usize, name: &str, value: AST) -> AST {
+    AST::Group(index, Some(String::from(name)), Box::new(value))
+}
+
+pub fn backref(index: usize) -> AST {
+    AST::Backref(index)
+}
+
+pub fn look(look: Look, value: AST) -> AST {
+    AST::Look(look, Box::new(value))
+}
+
+pub fn flags(flags: Flags, value: AST) -> AST {
+    AST::Flags(flags, Box::new(value))
+}
+
+// A ParseError carries a message and the Span of the pattern it refers to
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub msg: String,
+    pub span: Span,
+}
+
+impl ParseError {
+    pub fn new(msg: String, span: Span) -> ParseError {
+        ParseError { msg, span }
+    }
+
+    //renders the error followed by the pattern it came from with carets underlining the
+    //offending span, the same way compilers point at the bad part of a line of code
+    pub fn diagnostic(&self, pattern: &str) -> String {
+        let pad: String = pattern
+            .chars()
+            .take(self.span.start)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = std::cmp::max(1, self.span.end - self.span.start);
+        format!("{}\n    {}\n    {}{}", self, pattern, pad, "^".repeat(width))
+    }
+}
+
+// Columns are reported counting from 1, the way editors number them
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at column {}", self.msg, self.span.start + 1)
+    }
+}
+
 pub struct Parser<'tokens> {
     tokens: Peekable<Tokenizer<'tokens>>,
+    // column just past the last token taken, which is where end of input errors point
+    last: usize,
+    // flags in effect at the current token
+    flags: Flags,
+    // flags the AST being built already has a Flags node for
+    applied: Flags,
+    // number of groups opened so far, which is the number of the last one
+    groups: usize,
+    // names of the named groups opened so far
+    names: Vec<String>,
 }
 
 // Public parse function to establish parse tree
 impl<'tokens> Parser<'tokens> {
-    pub fn parse(tokenizer: Tokenizer<'tokens>) -> Result<AST, String> {
+    pub fn parse(tokenizer: Tokenizer<'tokens>) -> Result<AST, ParseError> {
+        Parser::parse_with_flags(tokenizer, Flags::default())
+    }
+
+    //parses with flags already set for the whole pattern, as thegrep's -i flag does
+    pub fn parse_with_flags(
+        tokenizer: Tokenizer<'tokens>,
+        flags: Flags,
+    ) -> Result<AST, ParseError> {
         let mut parser = Parser {
             tokens: tokenizer.peekable(),
+            last: 0,
+            flags,
+            applied: Flags::default(),
+            groups: 0,
+            names: vec![],
         };
-        let parse = parser.reg_expr();
+        let parse = parser.reg_expr()?;
         //checking to make sure the parser accounted for all tokens in input
-        let next = parser.take_next_token();
-        match next {
-            Ok(token) => Err(format!("Expected end of input, found {:?}", token)),
-            Err(_) => parse,
+        match parser.tokens.next() {
+            Some(next) => Err(unexpected(&next)),
+            None => Ok(parse),
         }
     }
 }
 
+//helper functions for implementing thegrep grammar
+impl<'tokens> Parser<'tokens> {
+    //reg_expr calls for a catenation and then checks to see if a union bar is present. If there
+    //is, reg_expr is called again and a alternation expression is created with the original expr
+    //and the one that was just retrieved with ast_two. If there is no union bar, then just the
+    //original expr is returned
+    fn reg_expr(&mut self) -> Result<AST, ParseError> {
+        let ast = self.cat()?;
+        if let Some(next) = self.tokens.peek() {
+            match next.token {
+                Token::UnionBar => {
+                    self.consume_token(Token::UnionBar)?;
+                    let ast_two = self.reg_expr()?;
+                    Ok(alternation(ast, ast_two))
+                }
+                _ => Ok(ast),
+            }
+        } else {
+            Ok(ast)
+        }
+    }
+
+    //cat calls clo and keeps working along the grammar, the result of calling clo is stored in
+    //closure. Next, we check to see if there is anything we need to catenate this value with. If
+    //there is any token that signifies a new atom, we call clo again. Otherwise, we simply return
+    //the "first" closure. Inline flags can come before or between closures, and when they change
+    //the flags in effect, the rest of the catenation is wrapped in a Flags node
+    fn cat(&mut self) -> Result<AST, ParseError> {
+        self.take_flags()?;
+        if self.flags != self.applied {
+            let outer = self.applied;
+            self.applied = self.flags;
+            let rest = self.cat();
+            let inner = self.applied;
+            self.applied = outer;
+            return Ok(flags(inner, rest?));
+        }
+        let closure = self.clo()?;
+        self.take_flags()?;
+        if let Some(next) = self.tokens.peek() {
+            match next.token {
+                Token::UnionBar => Ok(closure),
+                Token::LParen
+                | Token::AnyChar
+                | Token::Char(_)
+                | Token::Assertion(_)
+                | Token::Class(_)
+                | Token::Bracket(_)
+                | Token::Backref(_)
+                | Token::Look(_)
+                | Token::NamedGroup(_)
+                | Token::NonCapturing(_)
+                | Token::Error(_) => {
+                    let closure_two = self.cat()?;
+                    Ok(catenation(closure, closure_two))
+                }
+                _ => Ok(closure),
+            }
+        } else {
+            Ok(closure)
+        }
+    }
+
+    //clo calls atom and stores this in a variable. Next, we check to see if this atom has a kleene
+    //star after it. If so, we return a closure that enveloped this atom, otherwise, it simply
+    //returns the atom. A '*?' does the same but makes the closure lazy
+    fn clo(&mut self) -> Result<AST, ParseError> {
+        let atom = self.plus()?;
+        if let Some(kleene) = self.tokens.peek() {
+            match kleene.token {
+                Token::KleeneStar => {
+                    self.take_next_token()?;
+                    Ok(closure(atom))
+                }
+                Token::LazyStar => {
+                    self.take_next_token()?;
+                    Ok(lazy(closure(atom)))
+                }
+                _ => Ok(atom),
+            }
+        } else {
+            Ok(atom)
+        }
+    }
+
+    //plus does for '+' and '?' (and their lazy '+?' and '??') what clo does for '*'
+    fn plus(&mut self) -> Result<AST, ParseError> {
+        let atom = self.atom()?;
+        if let Some(plus) = self.tokens.peek() {
+            let quantified = match plus.token {
+                Token::KleenePlus => one_or_more(atom),
+                Token::LazyPlus => lazy(one_or_more(atom)),
+                Token::Question => optional(atom),
+                Token::LazyQuestion => lazy(optional(atom)),
+                _ => return Ok(atom),
+            };
+            self.take_next_token()?;
+            Ok(quantified)
+        } else {
+            Ok(atom)
+        }
+    }
+
+    //atom deals with the most basic building blocks of the grammar. If there is a Lparen, we look
+    //for the reg_expr inside of it and number the group, and a lookaround group is the same but
+    //without a number. A non-capturing group has no number either, and its flags only last until
+    //its ')'. If there is AnyChar, we return AnyChar, and if there is a char, an
+    //assertion, a class or a bracket expression we just return an AST object enveloping it. A
+    //named group is numbered like any other, and no two can share a name. A backreference has to
+    //come after the '(' of the group it refers to. Any other token cannot start an atom, so we
+    //report it as unexpected.
+    fn atom(&mut self) -> Result<AST, ParseError> {
+        let next = self.take_next_token()?;
+        match next.token {
+            Token::LParen => {
+                self.groups += 1;
+                let index = self.groups;
+                Ok(group(index, self.inside(&next)?))
+            }
+            Token::NamedGroup(ref name) if self.names.contains(name) => Err(ParseError::new(
+                format!("there is already a group named '{}'", name),
+                next.span,
+            )),
+            Token::NamedGroup(ref name) => {
+                self.groups += 1;
+                self.names.push(name.clone());
+                let index = self.groups;
+                Ok(named_group(index, name, self.inside(&next)?))
+            }
+            Token::NonCapturing(change) => {
+                let outer = self.flags;
+                self.flags = self.flags.apply(change);
+                let inside = self.inside(&next);
+                self.flags = outer;
+                inside
+            }
+            Token::Look(l) => Ok(look(l, self.inside(&next)?)),
+            Token::Backref(index) if index > self.groups => Err(ParseError::new(
+                format!("there is no group {} before '\\{}'", index, index),
+                next.span,
+            )),
+            Token::Backref(index) => Ok(backref(index)),
+            Token::AnyChar => Ok(AST::AnyChar),
+            Token::Char(c) => Ok(character(c)),
+            Token::Assertion(a) => Ok(assertion(a)),
+            Token::Class(c) => Ok(class(c)),
+            Token::Bracket(b) => Ok(bracket(b)),
+            _ => Err(unexpected(&next)),
+        }
+    }
+}
+
+//helper functions for parsing
+impl<'tokens> Parser<'tokens> {
+    //helper method for constructing parsers in unit tests
+    #[cfg(test)]
+    fn from(input: &'tokens str) -> Parser<'tokens> {
+        Parser {
+            tokens: Tokenizer::new(input).peekable(),
+            last: 0,
+            flags: Flags::default(),
+            applied: Flags::default(),
+            groups: 0,
+            names: vec![],
+        }
+    }
+
+    //this functions moves the iterator over the tokens forward and returns the token that was
+    //next, or returns an error if this method was called and there were no more tokens
+    fn take_next_token(&mut self) -> Result<Spanned, ParseError> {
+        if let Some(next) = self.tokens.next() {
+            self.last = next.span.end;
+            Ok(next)
+        } else {
+            Err(self.end_of_input())
+        }
+    }
+
+    //this function does the same thing as take_next_token except it allows us to pass in an
+    //expected value of the next token and if they don't match, returns an error, otherwise it
+    //returns the expected token (given it exists)
+    fn consume_token(&mut self, expected: Token) -> Result<Spanned, ParseError> {
+        let next = self.take_next_token()?;
+        if let Token::Error(_) = next.token {
+            Err(unexpected(&next))
+        } else if next.token != expected {
+            Err(ParseError::new(
+                format!("expected '{}', found '{}'", expected, next.token),
+                next.span,
+            ))
+        } else {
+            Ok(next)
+        }
+    }
+
+    //parses what is inside a group up to and including its ')'. Flags set inside of a group only
+    //last until the end of the group. A group that the input ends in, even right after its '(',
+    //is reported as unclosed
+    fn inside(&mut self, open: &Spanned) -> Result<AST, ParseError> {
+        let unclosed = || ParseError::new(format!("unclosed '{}'", open.token), open.span);
+        if self.tokens.peek().is_none() {
+            return Err(unclosed());
+        }
+        let outer = self.flags;
+        let reg_expr = self.reg_expr()?;
+        if self.tokens.peek().is_none() {
+            return Err(unclosed());
+        }
+        self.consume_token(Token::RParen)?;
+        self.flags = outer;
+        Ok(reg_expr)
+    }
+
+    //takes any inline flag tokens that are next and applies them to the flags in effect
+    fn take_flags(&mut self) -> Result<(), ParseError> {
+        loop {
+            let change = match self.tokens.peek() {
+                Some(Spanned {
+                    token: Token::Flags(change),
+                    ..
+                }) => *change,
+                _ => return Ok(()),
+            };
+            self.take_next_token()?;
+            self.flags = self.flags.apply(change);
+        }
+    }
+
+    //running out of tokens is reported one column past the last token we took
+    fn end_of_input(&self) -> ParseError {
+        ParseError::new(
+            String::from("unexpected end of input"),
+            Span {
+                start: self.last,
+                end: self.last + 1,
+            },
+        )
+    }
+}
+
+//error for a token that cannot appear where it was found. Error tokens already say what is
+//wrong with them
+fn unexpected(next: &Spanned) -> ParseError {
+    match &next.token {
+        Token::Error(msg) => ParseError::new(msg.clone(), next.span),
+        token => ParseError::new(format!("unexpected '{}'", token), next.span),
+    }
+}
+
 //Add Tests here
 
 #[cfg(test)]
@@ -71,7 +458,7 @@ mod public_api {
         let atom_any_char = Parser::parse(Tokenizer::new(".")).unwrap();
         assert_eq!(AST::AnyChar, atom_any_char);
         let atom_char_paren = Parser::parse(Tokenizer::new("(a)")).unwrap();
-        assert_eq!(character('a'), atom_char_paren);
+        assert_eq!(group(1, character('a')), atom_char_paren);
     }
 
     #[test]
@@ -81,7 +468,7 @@ mod public_api {
         let clo_any_char = Parser::parse(Tokenizer::new(".*")).unwrap();
         assert_eq!(closure(AST::AnyChar), clo_any_char);
         let clo_char_paren = Parser::parse(Tokenizer::new("(a)*")).unwrap();
-        assert_eq!(closure(character('a')), clo_char_paren);
+        assert_eq!(closure(group(1, character('a'))), clo_char_paren);
         let no_clo = Parser::parse(Tokenizer::new("a")).unwrap();
         assert_eq!(character('a'), no_clo);
     }
@@ -96,7 +483,7 @@ mod public_api {
         assert_eq!(catenation(AST::AnyChar, closure(character('b'))), cat_clo);
         let cat_clo_paren = Parser::parse(Tokenizer::new("(ab)*")).unwrap();
         assert_eq!(
-            closure(catenation(character('a'), character('b'))),
+            closure(group(1, catenation(character('a'), character('b')))),
             cat_clo_paren
         );
         let cat_mult = Parser::parse(Tokenizer::new("abc")).unwrap();
@@ -126,15 +513,21 @@ mod public_api {
         let alt_everything = Parser::parse(Tokenizer::new("((ab)*c)|(.a(b|c)*)")).unwrap();
         assert_eq!(
             alternation(
-                catenation(
-                    closure(catenation(character('a'), character('b'))),
-                    character('c')
+                group(
+                    1,
+                    catenation(
+                        closure(group(2, catenation(character('a'), character('b')))),
+                        character('c')
+                    )
                 ),
-                catenation(
-                    AST::AnyChar,
+                group(
+                    3,
                     catenation(
-                        character('a'),
-                        closure(alternation(character('b'), character('c')))
+                        AST::AnyChar,
+                        catenation(
+                            character('a'),
+                            closure(group(4, alternation(character('b'), character('c'))))
+                        )
                     )
                 )
             ),
@@ -144,6 +537,178 @@ mod public_api {
         assert_eq!(character('a'), no_alt);
     }
 
+    #[test]
+    fn errors() {
+        let trailing = Parser::parse(Tokenizer::new("(ab|c))")).unwrap_err();
+        assert_eq!(trailing.msg, "unexpected ')'");
+        assert_eq!(trailing.span, Span { start: 6, end: 7 });
+        assert_eq!(trailing.to_string(), "unexpected ')' at column 7");
+        let dangling = Parser::parse(Tokenizer::new("a|")).unwrap_err();
+        assert_eq!(dangling.to_string(), "unexpected end of input at column 3");
+        let unclosed = Parser::parse(Tokenizer::new("x(ab")).unwrap_err();
+        assert_eq!(unclosed.to_string(), "unclosed '(' at column 2");
+        let bare = Parser::parse(Tokenizer::new("(")).unwrap_err();
+        assert_eq!(bare.to_string(), "unclosed '(' at column 1");
+        let bare = Parser::parse(Tokenizer::new("ab(")).unwrap_err();
+        assert_eq!(bare.to_string(), "unclosed '(' at column 3");
+        let bare = Parser::parse(Tokenizer::new("a(?=")).unwrap_err();
+        assert_eq!(bare.to_string(), "unclosed '(?=' at column 2");
+        let star = Parser::parse(Tokenizer::new("a|*b")).unwrap_err();
+        assert_eq!(star.to_string(), "unexpected '*' at column 3");
+        let empty = Parser::parse(Tokenizer::new("")).unwrap_err();
+        assert_eq!(empty.to_string(), "unexpected end of input at column 1");
+    }
+
+    #[test]
+    fn parse_flags() {
+        let i = Flags {
+            case_insensitive: true,
+            ..Flags::default()
+        };
+        let parsed = Parser::parse(Tokenizer::new("(?i)ab")).unwrap();
+        assert_eq!(flags(i, catenation(character('a'), character('b'))), parsed);
+        let parsed = Parser::parse(Tokenizer::new("a(?i)b|c")).unwrap();
+        assert_eq!(
+            alternation(
+                catenation(character('a'), flags(i, character('b'))),
+                flags(i, character('c'))
+            ),
+            parsed
+        );
+        let parsed = Parser::parse(Tokenizer::new("((?i)a)b")).unwrap();
+        assert_eq!(catenation(group(1, flags(i, character('a'))), character('b')), parsed);
+        let parsed = Parser::parse(Tokenizer::new("a(?i)")).unwrap();
+        assert_eq!(character('a'), parsed);
+        let parsed = Parser::parse_with_flags(Tokenizer::new("a(?-i)b"), i).unwrap();
+        assert_eq!(
+            flags(i, catenation(character('a'), flags(Flags::default(), character('b')))),
+            parsed
+        );
+    }
+
+    #[test]
+    fn parse_escapes() {
+        use crate::tokenizer::{BracketItem, ClassKind};
+        let parsed = Parser::parse(Tokenizer::new("\\ba\\B")).unwrap();
+        assert_eq!(
+            catenation(
+                assertion(Assertion::WordBoundary),
+                catenation(character('a'), assertion(Assertion::NotWordBoundary))
+            ),
+            parsed
+        );
+        let parsed = Parser::parse(Tokenizer::new("\\(a\\)")).unwrap();
+        assert_eq!(catenation(character('('), catenation(character('a'), character(')'))), parsed);
+        let parsed = Parser::parse(Tokenizer::new("\\d*")).unwrap();
+        let digit = Class {
+            kind: ClassKind::Digit,
+            negated: false,
+        };
+        assert_eq!(closure(class(digit)), parsed);
+        let parsed = Parser::parse(Tokenizer::new("[ab]*")).unwrap();
+        let ab = Bracket {
+            negated: false,
+            items: vec![BracketItem::Range('a', 'a'), BracketItem::Range('b', 'b')],
+        };
+        assert_eq!(closure(bracket(ab)), parsed);
+        let err = Parser::parse(Tokenizer::new("a\\")).unwrap_err();
+        assert_eq!(err.to_string(), "trailing backslash at column 2");
+    }
+
+    #[test]
+    fn parse_backrefs() {
+        let parsed = Parser::parse(Tokenizer::new("(a)(b)\\2")).unwrap();
+        assert_eq!(
+            catenation(group(1, character('a')), catenation(group(2, character('b')), backref(2))),
+            parsed
+        );
+        let parsed = Parser::parse(Tokenizer::new("(a\\1)")).unwrap();
+        assert_eq!(group(1, catenation(character('a'), backref(1))), parsed);
+        let err = Parser::parse(Tokenizer::new("\\1(a)")).unwrap_err();
+        assert_eq!(err.to_string(), "there is no group 1 before '\\1' at column 1");
+        assert_eq!(err.span, Span { start: 0, end: 2 });
+    }
+
+    #[test]
+    fn parse_non_capturing() {
+        let i = Flags {
+            case_insensitive: true,
+            ..Flags::default()
+        };
+        let parsed = Parser::parse(Tokenizer::new("(?:a)(b)")).unwrap();
+        assert_eq!(catenation(character('a'), group(1, character('b'))), parsed);
+        let parsed = Parser::parse(Tokenizer::new("(?i:a)b")).unwrap();
+        assert_eq!(catenation(flags(i, character('a')), character('b')), parsed);
+        let parsed = Parser::parse(Tokenizer::new("(?s:(?-i:.))")).unwrap();
+        let s = Flags {
+            dot_all: true,
+            ..Flags::default()
+        };
+        assert_eq!(flags(s, AST::AnyChar), parsed);
+        let parsed = Parser::parse(Tokenizer::new("(?x: a | b )c")).unwrap();
+        assert_eq!(catenation(alternation(character('a'), character('b')), character('c')), parsed);
+        let err = Parser::parse(Tokenizer::new("(?i:a")).unwrap_err();
+        assert_eq!(err.to_string(), "unclosed '(?i:' at column 1");
+    }
+
+    #[test]
+    fn parse_named_groups() {
+        let parsed = Parser::parse(Tokenizer::new("(?P<user>a)(b)")).unwrap();
+        assert_eq!(catenation(named_group(1, "user", character('a')), group(2, character('b'))), parsed);
+        let err = Parser::parse(Tokenizer::new("(?P<x>a)(?P<x>b)")).unwrap_err();
+        assert_eq!(err.to_string(), "there is already a group named 'x' at column 9");
+        assert_eq!(err.span, Span { start: 8, end: 14 });
+        let err = Parser::parse(Tokenizer::new("(?P<x>a")).unwrap_err();
+        assert_eq!(err.msg, "unclosed '(?P<x>'");
+    }
+
+    #[test]
+    fn parse_looks() {
+        let ahead = Look {
+            behind: false,
+            negated: false,
+        };
+        let not_behind = Look {
+            behind: true,
+            negated: true,
+        };
+        let parsed = Parser::parse(Tokenizer::new("(?<!a)b(?=(c)*)")).unwrap();
+        assert_eq!(
+            catenation(
+                look(not_behind, character('a')),
+                catenation(character('b'), look(ahead, closure(group(1, character('c')))))
+            ),
+            parsed
+        );
+        let err = Parser::parse(Tokenizer::new("a(?<=b")).unwrap_err();
+        assert_eq!(err.to_string(), "unclosed '(?<=' at column 2");
+        assert_eq!(err.span, Span { start: 1, end: 5 });
+    }
+
+    #[test]
+    fn flag_errors() {
+        let err = Parser::parse(Tokenizer::new("a(?z)")).unwrap_err();
+        assert_eq!(err.to_string(), "unknown flag 'z' at column 4");
+        let err = Parser::parse(Tokenizer::new("(a(?i")).unwrap_err();
+        assert_eq!(err.to_string(), "unclosed flag group at column 3");
+        let err = Parser::parse(Tokenizer::new("(?i)")).unwrap_err();
+        assert_eq!(err.to_string(), "unexpected end of input at column 5");
+    }
+
+    #[test]
+    fn diagnostic() {
+        let err = Parser::parse(Tokenizer::new("(ab|c))")).unwrap_err();
+        assert_eq!(
+            err.diagnostic("(ab|c))"),
+            "unexpected ')' at column 7\n    (ab|c))\n          ^"
+        );
+        let err = Parser::parse(Tokenizer::new("\t(a")).unwrap_err();
+        assert_eq!(
+            err.diagnostic("\t(a"),
+            "unclosed '(' at column 2\n    \t(a\n    \t^"
+        );
+    }
+
     #[test]
     fn reg_expr() {
         assert_eq!(Parser::from("a").reg_expr().unwrap(), character('a'));
@@ -165,15 +730,21 @@ mod public_api {
         assert_eq!(
             Parser::from("((ab)*c)|(.a(b|c)*)").reg_expr().unwrap(),
             alternation(
-                catenation(
-                    closure(catenation(character('a'), character('b'))),
-                    character('c')
+                group(
+                    1,
+                    catenation(
+                        closure(group(2, catenation(character('a'), character('b')))),
+                        character('c')
+                    )
                 ),
-                catenation(
-                    AST::AnyChar,
+                group(
+                    3,
                     catenation(
-                        character('a'),
-                        closure(alternation(character('b'), character('c')))
+                        AST::AnyChar,
+                        catenation(
+                            character('a'),
+                            closure(group(4, alternation(character('b'), character('c'))))
+                        )
                     )
                 )
             )
@@ -192,7 +763,7 @@ mod public_api {
         );
         assert_eq!(
             Parser::from("(ab)*").cat().unwrap(),
-            closure(catenation(character('a'), character('b')))
+            closure(group(1, catenation(character('a'), character('b'))))
         );
         assert_eq!(
             Parser::from("abc").cat().unwrap(),
@@ -205,145 +776,30 @@ mod public_api {
     fn clo() {
         assert_eq!(Parser::from("a*").clo().unwrap(), closure(character('a')));
         assert_eq!(Parser::from(".*").clo().unwrap(), closure(AST::AnyChar));
-        assert_eq!(Parser::from("(a)*").clo().unwrap(), closure(character('a')));
+        assert_eq!(Parser::from("(a)*").clo().unwrap(), closure(group(1, character('a'))));
         assert_eq!(Parser::from("a").clo().unwrap(), character('a'));
+        assert_eq!(Parser::from("a*?").clo().unwrap(), lazy(closure(character('a'))));
+    }
+
+    #[test]
+    fn plus() {
+        assert_eq!(Parser::from("a+").plus().unwrap(), one_or_more(character('a')));
+        assert_eq!(Parser::from("a?").plus().unwrap(), optional(character('a')));
+        assert_eq!(
+            Parser::from("a+?").plus().unwrap(),
+            AST::OneOrMore(Box::new(character('a')), Greed::Lazy)
+        );
+        assert_eq!(
+            Parser::from("a??").plus().unwrap(),
+            AST::Optional(Box::new(character('a')), Greed::Lazy)
+        );
+        assert_eq!(Parser::from("a+*").clo().unwrap(), closure(one_or_more(character('a'))));
     }
 
     #[test]
     fn atom() {
         assert_eq!(Parser::from("a").atom().unwrap(), character('a'));
         assert_eq!(Parser::from(".").atom().unwrap(), AST::AnyChar);
-        assert_eq!(Parser::from("(a)").atom().unwrap(), character('a'));
-    }
-
-
-}
-
-//helper functions for implementing thegrep grammar
-impl<'tokens> Parser<'tokens> {
-    //reg_expr calls for a catenation and then checks to see if a union bar is present. If there
-    //is, reg_expr is called again and a alternation expression is created with the original expr
-    //and the one that was just retrieved with ast_two. If there is no union bar, then just the
-    //original expr is returned
-    fn reg_expr(&mut self) -> Result<AST, String> {
-        let ast = self.cat()?;
-        if let Some(token) = self.tokens.peek() {
-            match token {
-                Token::UnionBar => {
-                    self.consume_token(Token::UnionBar)?;
-                    let ast_two = self.reg_expr()?;
-                    Ok(alternation(ast, ast_two))
-                }
-                _ => Ok(ast),
-            }
-        } else {
-            Ok(ast)
-        }
-    }
-
-    //cat calls clo and keeps working along the grammar, the result of calling clo is stored in
-    //closure. Next, we check to see if there is anything we need to catenate this value with. If
-    //there is any token that signifies a new atom, we call clo again. Otherwise, we simply return
-    //the "first" closure
-    fn cat(&mut self) -> Result<AST, String> {
-        let closure = self.clo()?;
-        if let Some(token) = self.tokens.peek() {
-            match token {
-                Token::UnionBar => Ok(closure),
-                Token::LParen | Token::AnyChar | Token::Char(_) => {
-                    let closure_two = self.cat()?;
-                    Ok(catenation(closure, closure_two))
-                }
-                _ => Ok(closure),
-            }
-        } else {
-            Ok(closure)
-        }
-    }
-
-    //clo calls atom and stores this in a variable. Next, we check to see if this atom has a kleene
-    //star after it. If so, we return a closure that enveloped this atom, otherwise, it simply
-    //returns the atom
-    fn clo(&mut self) -> Result<AST, String> {
-        let atom = self.plus()?;
-        if let Some(kleene) = self.tokens.peek() {
-            match kleene {
-                Token::KleeneStar => {
-                    self.take_next_token()?;
-                    Ok(closure(atom))
-                }
-                _ => Ok(atom),
-            }
-        } else {
-            Ok(atom)
-        }
-    }
-
-    fn plus(&mut self) -> Result<AST, String> {
-        let atom = self.atom()?;
-        if let Some(plus) = self.tokens.peek() {
-            match plus {
-                Token::KleenePlus => {
-                    self.take_next_token()?;
-                    Ok(one_or_more(atom))
-                }
-                _ => Ok(atom),
-            }
-        } else {
-            Ok(atom)
-        }
-    }
-
-    //atom deals with the most basic building blocks of the grammar. If there is a Lparen, we look
-    //for the reg_expr inside of it, if there is AnyChar, we return AnyChar, and if there is a char
-    //we just return an AST char object enveloping the character.
-    fn atom(&mut self) -> Result<AST, String> {
-        let next = self.take_next_token();
-        match next {
-            Ok(Token::LParen) => {
-                let reg_expr = self.reg_expr()?;
-                self.consume_token(Token::RParen)?;
-                Ok(reg_expr)
-            }
-            Ok(Token::AnyChar) => Ok(AST::AnyChar),
-            Ok(Token::Char(c)) => Ok(character(c)),
-            _ => Err(format!("Unexpected end of input")),
-        }
-    }
-}
-
-//helper functions for parsing
-impl<'tokens> Parser<'tokens> {
-
-    //helper method for constructing parsers in unit tests
-    fn from(input: &'tokens str) -> Parser<'tokens> {
-        Parser {
-            tokens: Tokenizer::new(input).peekable(),
-        }
-    }
-    
-    //this functions moves the iterator over the tokens forward and returns the token that was
-    //next, or returns an error if this method was called and there were no more tokens
-    fn take_next_token(&mut self) -> Result<Token, String> {
-        if let Some(token) = self.tokens.next() {
-            Ok(token)
-        } else {
-            Err(String::from("Unexpected end of input"))
-        }
-    }
-
-    //this function does the same thing as take_next_token except it allows us to pass in an
-    //expected value of the next token and if they don't match, returns an error, otherwise it
-    //returns the expected token (given it exists)
-    fn consume_token(&mut self, expected: Token) -> Result<Token, String> {
-        if let Some(next) = self.tokens.next() {
-            if next != expected {
-                Err(format!("Expected: {:?} - Found {:?}", expected, next))
-            } else {
-                Ok(next)
-            }
-        } else {
-            Err(String::from("Unexpected end of input"))
-        }
+        assert_eq!(Parser::from("(a)").atom().unwrap(), group(1, character('a')));
     }
 }
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+/**
+ * Run work on every item across a pool of threads, handing each item
+ * and its result to done on the calling thread in the order of the
+ * items, whatever order they finish in. Results that finish early wait
+ * in a buffer until every item before them is done.
+ *
+ * done returns whether to keep going. Once it says to stop, no more items
+ * are started and the results of those already running are dropped.
+ */
+pub fn ordered<T, R, F, D>(items: &[T], threads: usize, work: F, mut done: D)
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+    D: FnMut(&T, R) -> bool,
+{
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1).min(items.len()) {
+            let sender = sender.clone();
+            let (next, stop, work) = (&next, &stop, &work);
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= items.len() || sender.send((i, work(&items[i]))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        //the workers hold the only senders left, so the results end when they do
+        drop(sender);
+        let mut waiting = HashMap::new();
+        let mut wanted = 0;
+        for (i, result) in receiver.iter() {
+            waiting.insert(i, result);
+            while let Some(result) = waiting.remove(&wanted) {
+                wanted += 1;
+                if !done(&items[wanted - 1], result) {
+                    stop.store(true, Ordering::Relaxed);
+                    return;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn results_in_order() {
+        let items: Vec<u64> = (0..20).collect();
+        let mut results = vec![];
+        //later items finish first, so they have to wait for the earlier ones
+        ordered(
+            &items,
+            4,
+            |i| {
+                thread::sleep(Duration::from_millis(20 - i));
+                i * 2
+            },
+            |i, result| {
+                assert_eq!(*i * 2, result);
+                results.push(result);
+                true
+            },
+        );
+        assert_eq!(results, items.iter().map(|i| i * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn stops_early() {
+        let items: Vec<u64> = (0..1000).collect();
+        let started = AtomicUsize::new(0);
+        let mut results = vec![];
+        ordered(
+            &items,
+            2,
+            |i| {
+                started.fetch_add(1, Ordering::Relaxed);
+                thread::sleep(Duration::from_millis(1));
+                *i
+            },
+            |_, result| {
+                results.push(result);
+                result < 3
+            },
+        );
+        assert_eq!(results, vec![0, 1, 2, 3]);
+        assert!(started.load(Ordering::Relaxed) < 1000);
+    }
+
+    #[test]
+    fn no_items() {
+        let items: Vec<u64> = vec![];
+        ordered(&items, 8, |i| *i, |_, _| panic!("there is nothing to finish"));
+    }
+}
@@ -0,0 +1,566 @@
+use super::matcher::{FindIter, Matcher};
+use super::regex::Template;
+use super::search::{Line, Sink};
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+/**
+ * What the Printer reports about each file searched.
+ * - Lines prints every selected line
+ * - Count prints how many lines were selected
+ * - FilesWithMatches prints the names of files with a selected line
+ * - FilesWithoutMatch prints the names of files without one
+ * - Quiet prints nothing at all
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    Lines,
+    Count,
+    FilesWithMatches,
+    FilesWithoutMatch,
+    Quiet,
+}
+
+/**
+ * When to color output: always, never, or only when it is going to a
+ * terminal, which is left to whoever creates the Printer to work out.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ColorChoice, String> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("unknown color choice '{}'", s)),
+        }
+    }
+}
+
+//the SGR parameters grep colors matches, file names, numbers and separators with by default
+const MATCH_COLOR: &str = "01;31";
+const PATH_COLOR: &str = "35";
+const NUMBER_COLOR: &str = "32";
+const SEPARATOR_COLOR: &str = "36";
+
+/**
+ * Options that decide what the Printer prints and what it prefixes
+ * each printed line with, separated by colons in the order below.
+ * - output is what gets reported about each file
+ * - with_filename prefixes the name of the file
+ * - line_number prefixes the line's number
+ * - column prefixes the column of the first match, counting bytes from 1
+ * - byte_offset prefixes the byte offset of the start of the line
+ * - which prefixes selected lines with the numbers of the patterns that
+ *   match them, counting from 1 and separated by commas
+ * - only_matching prints each match in a selected line on its own line,
+ *   with its own column and byte offset, instead of the whole line
+ * - replace prints the matches in selected lines as the Template makes
+ *   them, rather than as they are
+ * - color highlights matches and prefixes with ANSI escapes
+ */
+#[derive(Debug, Clone)]
+pub struct PrintOptions {
+    pub output: Output,
+    pub with_filename: bool,
+    pub line_number: bool,
+    pub column: bool,
+    pub byte_offset: bool,
+    pub which: bool,
+    pub only_matching: bool,
+    pub replace: Option<Template>,
+    pub color: bool,
+}
+
+impl Default for PrintOptions {
+    fn default() -> PrintOptions {
+        PrintOptions {
+            output: Output::Lines,
+            with_filename: false,
+            line_number: false,
+            column: false,
+            byte_offset: false,
+            which: false,
+            only_matching: false,
+            replace: None,
+            color: false,
+        }
+    }
+}
+
+/**
+ * The Printer writes the results of searches to out. It lives across
+ * every file searched, handing out a Sink for each one. The Matcher is
+ * the one lines were selected with, used to find where they match.
+ */
+pub struct Printer<'m, W: Write> {
+    out: W,
+    matcher: &'m dyn Matcher,
+    options: PrintOptions,
+    printed: bool,
+    leading_break: bool,
+}
+
+impl<'m, W: Write> Printer<'m, W> {
+    pub fn new(out: W, matcher: &'m dyn Matcher, options: PrintOptions) -> Printer<'m, W> {
+        Printer {
+            out,
+            matcher,
+            options,
+            printed: false,
+            leading_break: false,
+        }
+    }
+
+    /**
+     * A Sink that prints the results of searching the file named path.
+     */
+    pub fn sink<'p>(&'p mut self, path: &'p str) -> PrinterSink<'p, 'm, W> {
+        PrinterSink {
+            printer: self,
+            path,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /**
+     * Print what another Printer with the same options buffered, as if
+     * its searches had been printed by this one. This is how files
+     * searched on other threads are printed in order.
+     */
+    pub fn append(&mut self, buffered: Printer<'m, Vec<u8>>) -> io::Result<()> {
+        if buffered.leading_break && self.printed {
+            self.separator()?;
+        }
+        self.out.write_all(&buffered.out)?;
+        self.printed |= buffered.printed;
+        Ok(())
+    }
+
+    fn separator(&mut self) -> io::Result<()> {
+        paint(&mut self.out, self.options.color, SEPARATOR_COLOR, b"--")?;
+        self.out.write_all(b"\n")
+    }
+}
+
+/**
+ * The Sink a Printer hands out for a single file.
+ */
+pub struct PrinterSink<'p, 'm, W: Write> {
+    printer: &'p mut Printer<'m, W>,
+    path: &'p str,
+}
+
+impl<'p, 'm, W: Write> PrinterSink<'p, 'm, W> {
+    /**
+     * Print a line. Prefixes of selected lines end in ':' and those of
+     * context in '-', and only selected lines have their matches
+     * highlighted, replaced or printed on their own.
+     */
+    fn print(&mut self, line: &Line, sep: &str) -> io::Result<()> {
+        let matcher = self.printer.matcher;
+        let selected = sep == ":";
+        if selected && self.printer.options.only_matching {
+            for (start, end) in FindIter::new(matcher, line.bytes).filter(|(s, e)| s < e) {
+                self.prefix(line, sep, start, &line.bytes[start..end])?;
+                let text = self.replaced(line, start, end);
+                let out = &mut self.printer.out;
+                paint(out, self.printer.options.color, MATCH_COLOR, &text)?;
+                out.write_all(b"\n")?;
+            }
+        } else {
+            //a line without a match, like an inverted one, has its column at the start
+            let column = matcher.find(line.bytes).map_or(0, |(start, _)| start);
+            self.prefix(line, sep, column, line.bytes)?;
+            let options = &self.printer.options;
+            let mut printed = 0;
+            if selected && (options.color || options.replace.is_some()) {
+                for (start, end) in FindIter::new(matcher, line.bytes).filter(|(s, e)| s < e) {
+                    let text = self.replaced(line, start, end);
+                    let out = &mut self.printer.out;
+                    out.write_all(&line.bytes[printed..start])?;
+                    paint(out, self.printer.options.color, MATCH_COLOR, &text)?;
+                    printed = end;
+                }
+            }
+            let out = &mut self.printer.out;
+            out.write_all(&line.bytes[printed..])?;
+            out.write_all(b"\n")?;
+        }
+        self.printer.printed = true;
+        Ok(())
+    }
+
+    /**
+     * The text to print for the match from start to end in line, which is
+     * the match itself unless the options have a Template to replace it.
+     */
+    fn replaced<'l>(&self, line: &Line<'l>, start: usize, end: usize) -> Cow<'l, [u8]> {
+        match &self.printer.options.replace {
+            Some(template) => {
+                let mut text = vec![];
+                template.expand(self.printer.matcher, line.bytes, start, end, &mut text);
+                Cow::Owned(text)
+            }
+            None => Cow::Borrowed(&line.bytes[start..end]),
+        }
+    }
+
+    fn print_path(&mut self) -> io::Result<()> {
+        let out = &mut self.printer.out;
+        paint(out, self.printer.options.color, PATH_COLOR, self.path.as_bytes())?;
+        out.write_all(b"\n")
+    }
+
+    /**
+     * Write the prefixes the options ask for ahead of a line, or of the
+     * match in it that starts at the byte offset start. Which patterns
+     * match is worked out from text, the part of the line being printed.
+     */
+    fn prefix(&mut self, line: &Line, sep: &str, start: usize, text: &[u8]) -> io::Result<()> {
+        let options = &self.printer.options;
+        let out = &mut self.printer.out;
+        let color = options.color;
+        if options.with_filename {
+            paint(out, color, PATH_COLOR, self.path.as_bytes())?;
+            paint(out, color, SEPARATOR_COLOR, sep.as_bytes())?;
+        }
+        if options.line_number {
+            paint(out, color, NUMBER_COLOR, line.number.to_string().as_bytes())?;
+            paint(out, color, SEPARATOR_COLOR, sep.as_bytes())?;
+        }
+        if options.column {
+            paint(out, color, NUMBER_COLOR, (start + 1).to_string().as_bytes())?;
+            paint(out, color, SEPARATOR_COLOR, sep.as_bytes())?;
+        }
+        if options.byte_offset {
+            let offset = if options.only_matching {
+                line.offset + start as u64
+            } else {
+                line.offset
+            };
+            paint(out, color, NUMBER_COLOR, offset.to_string().as_bytes())?;
+            paint(out, color, SEPARATOR_COLOR, sep.as_bytes())?;
+        }
+        if options.which && sep == ":" {
+            let which: Vec<_> = self.printer.matcher.which(text).iter().map(|i| (i + 1).to_string()).collect();
+            paint(out, color, NUMBER_COLOR, which.join(",").as_bytes())?;
+            paint(out, color, SEPARATOR_COLOR, sep.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Write text to out, wrapped in the escapes for an SGR color when color
+ * is on. Text is raw bytes so that lines in other encodings pass through
+ * untouched.
+ */
+fn paint<W: Write>(out: &mut W, color: bool, sgr: &str, text: &[u8]) -> io::Result<()> {
+    if color {
+        write!(out, "\x1b[{}m\x1b[K", sgr)?;
+        out.write_all(text)?;
+        write!(out, "\x1b[m\x1b[K")
+    } else {
+        out.write_all(text)
+    }
+}
+
+impl<'p, 'm, W: Write> Sink for PrinterSink<'p, 'm, W> {
+    fn matched(&mut self, line: &Line) -> io::Result<bool> {
+        match self.printer.options.output {
+            //the first match in a binary file is all we report for it
+            Output::Lines if line.binary => {
+                writeln!(self.printer.out, "Binary file {} matches", self.path)?;
+                Ok(false)
+            }
+            Output::Lines => {
+                self.print(line, ":")?;
+                Ok(true)
+            }
+            Output::Count => Ok(true),
+            Output::FilesWithMatches => {
+                self.print_path()?;
+                Ok(false)
+            }
+            Output::FilesWithoutMatch | Output::Quiet => Ok(false),
+        }
+    }
+
+    fn context(&mut self, line: &Line) -> io::Result<()> {
+        match self.printer.options.output {
+            Output::Lines if !line.binary => self.print(line, "-"),
+            _ => Ok(()),
+        }
+    }
+
+    //groups are separated across files too, so nothing is printed ahead of the very first. Until
+    //something is printed, the break is remembered in case this output is appended to another's
+    fn context_break(&mut self) -> io::Result<()> {
+        if self.printer.printed {
+            self.printer.separator()
+        } else {
+            self.printer.leading_break = true;
+            Ok(())
+        }
+    }
+
+    fn finish(&mut self, matches: u64) -> io::Result<()> {
+        let color = self.printer.options.color;
+        match self.printer.options.output {
+            Output::Count => {
+                let out = &mut self.printer.out;
+                if self.printer.options.with_filename {
+                    paint(out, color, PATH_COLOR, self.path.as_bytes())?;
+                    paint(out, color, SEPARATOR_COLOR, b":")?;
+                }
+                writeln!(out, "{}", matches)
+            }
+            Output::FilesWithoutMatch if matches == 0 => self.print_path(),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfa::NFA;
+    use crate::search::{SearchOptions, Searcher};
+
+    //searches each (path, input) pair in turn and returns everything printed
+    fn print(pattern: &str, output: Output, files: &[(&str, &str)]) -> String {
+        let options = PrintOptions {
+            output,
+            ..PrintOptions::default()
+        };
+        print_with(pattern, options, files)
+    }
+
+    fn print_with(pattern: &str, options: PrintOptions, files: &[(&str, &str)]) -> String {
+        search_with(pattern, SearchOptions::default(), options, files)
+    }
+
+    fn search_with(
+        pattern: &str,
+        search: SearchOptions,
+        options: PrintOptions,
+        files: &[(&str, &str)],
+    ) -> String {
+        let nfa = NFA::from(pattern).unwrap();
+        let searcher = Searcher::new(&nfa, search);
+        let mut printer = Printer::new(vec![], &nfa, options);
+        for (path, input) in files {
+            searcher
+                .search(input.as_bytes(), &mut printer.sink(path))
+                .unwrap();
+        }
+        String::from_utf8(printer.into_inner()).unwrap()
+    }
+
+    const FILES: &[(&str, &str)] = &[("a.txt", "one\ntwo\nthree\n"), ("b.txt", "four\n")];
+
+    #[test]
+    fn lines() {
+        assert_eq!(print("o", Output::Lines, FILES), "one\ntwo\nfour\n");
+    }
+
+    #[test]
+    fn count() {
+        assert_eq!(print("t", Output::Count, FILES), "2\n0\n");
+    }
+
+    #[test]
+    fn files_with_matches() {
+        assert_eq!(print("e", Output::FilesWithMatches, FILES), "a.txt\n");
+        assert_eq!(print("o", Output::FilesWithMatches, FILES), "a.txt\nb.txt\n");
+    }
+
+    #[test]
+    fn files_without_match() {
+        assert_eq!(print("e", Output::FilesWithoutMatch, FILES), "b.txt\n");
+        assert_eq!(print("o", Output::FilesWithoutMatch, FILES), "");
+    }
+
+    #[test]
+    fn quiet() {
+        assert_eq!(print("o", Output::Quiet, FILES), "");
+    }
+
+    #[test]
+    fn binary() {
+        let files = &[("bin", "a\0b\na\n")];
+        assert_eq!(print("a", Output::Lines, files), "Binary file bin matches\n");
+        assert_eq!(print("a", Output::Count, files), "2\n");
+    }
+
+    #[test]
+    fn prefixes() {
+        let options = PrintOptions {
+            with_filename: true,
+            line_number: true,
+            ..PrintOptions::default()
+        };
+        assert_eq!(print_with("o", options, FILES), "a.txt:1:one\na.txt:2:two\nb.txt:1:four\n");
+        let options = PrintOptions {
+            column: true,
+            byte_offset: true,
+            ..PrintOptions::default()
+        };
+        assert_eq!(print_with("o", options, FILES), "1:0:one\n3:4:two\n2:0:four\n");
+    }
+
+    #[test]
+    fn count_with_filename() {
+        let options = PrintOptions {
+            output: Output::Count,
+            with_filename: true,
+            ..PrintOptions::default()
+        };
+        assert_eq!(print_with("t", options, FILES), "a.txt:2\nb.txt:0\n");
+    }
+
+    #[test]
+    fn context() {
+        let files = &[
+            ("a.log", "ok\nwarn\nerror 1\nok\nok\nok\nerror 2\n"),
+            ("b.log", "error 3\nok\n"),
+        ];
+        let search = SearchOptions {
+            before_context: 1,
+            after_context: 1,
+            ..SearchOptions::default()
+        };
+        let options = PrintOptions {
+            with_filename: true,
+            line_number: true,
+            ..PrintOptions::default()
+        };
+        assert_eq!(
+            search_with("error", search, options, files),
+            "a.log-2-warn\n\
+             a.log:3:error 1\n\
+             a.log-4-ok\n\
+             --\n\
+             a.log-6-ok\n\
+             a.log:7:error 2\n\
+             --\n\
+             b.log:1:error 3\n\
+             b.log-2-ok\n"
+        );
+    }
+
+    #[test]
+    fn only_matching() {
+        let options = PrintOptions {
+            only_matching: true,
+            column: true,
+            byte_offset: true,
+            ..PrintOptions::default()
+        };
+        let files = &[("a.txt", "x\nfoo bar foo\n")];
+        assert_eq!(print_with("fo+|bar", options, files), "1:2:foo\n5:6:bar\n9:10:foo\n");
+        let options = PrintOptions {
+            only_matching: true,
+            ..PrintOptions::default()
+        };
+        assert_eq!(print_with("b*", options, files), "b\n");
+    }
+
+    #[test]
+    fn replace() {
+        let options = PrintOptions {
+            replace: Some(Template::new("<$2 $1>")),
+            ..PrintOptions::default()
+        };
+        let files = &[("a.txt", "x\nann smith, bo li\n")];
+        assert_eq!(print_with("(\\w+) (\\w+)", options.clone(), files), "<smith ann>, <li bo>\n");
+        let options = PrintOptions {
+            only_matching: true,
+            line_number: true,
+            ..options
+        };
+        assert_eq!(print_with("(\\w+) (\\w+)", options, files), "2:<smith ann>\n2:<li bo>\n");
+        let options = PrintOptions {
+            replace: Some(Template::new("[$0]")),
+            color: true,
+            ..PrintOptions::default()
+        };
+        assert_eq!(print_with("o", options, &[("b.txt", "four\n")]), "f\x1b[01;31m\x1b[K[o]\x1b[m\x1b[Kur\n");
+    }
+
+    #[test]
+    fn which() {
+        let nfa = NFA::with_patterns(&["error", "disk", "x+"], Default::default()).unwrap();
+        let options = PrintOptions {
+            which: true,
+            line_number: true,
+            ..PrintOptions::default()
+        };
+        let mut printer = Printer::new(vec![], &nfa, options);
+        let input = b"disk error\nok\nxx\n";
+        Searcher::new(&nfa, SearchOptions::default())
+            .search(&input[..], &mut printer.sink(""))
+            .unwrap();
+        let printed = String::from_utf8(printer.into_inner()).unwrap();
+        assert_eq!(printed, "1:1,2:disk error\n3:3:xx\n");
+    }
+
+    #[test]
+    fn color() {
+        let options = PrintOptions {
+            color: true,
+            with_filename: true,
+            line_number: true,
+            ..PrintOptions::default()
+        };
+        assert_eq!(
+            print_with("o", options, &[("b.txt", "four\n")]),
+            "\x1b[35m\x1b[Kb.txt\x1b[m\x1b[K\x1b[36m\x1b[K:\x1b[m\x1b[K\
+             \x1b[32m\x1b[K1\x1b[m\x1b[K\x1b[36m\x1b[K:\x1b[m\x1b[K\
+             f\x1b[01;31m\x1b[Ko\x1b[m\x1b[Kur\n"
+        );
+    }
+
+    #[test]
+    fn color_keeps_raw_bytes() {
+        let nfa = NFA::from("caf").unwrap();
+        let options = PrintOptions {
+            color: true,
+            ..PrintOptions::default()
+        };
+        let mut printer = Printer::new(vec![], &nfa, options);
+        Searcher::new(&nfa, SearchOptions::default())
+            .search(&b"caf\xe9\n"[..], &mut printer.sink("latin1"))
+            .unwrap();
+        assert_eq!(printer.into_inner(), b"\x1b[01;31m\x1b[Kcaf\x1b[m\x1b[K\xe9\n".to_vec());
+    }
+
+    #[test]
+    fn append() {
+        let nfa = NFA::from("x").unwrap();
+        let search = SearchOptions {
+            after_context: 1,
+            ..SearchOptions::default()
+        };
+        let searcher = Searcher::new(&nfa, search);
+        let mut printer = Printer::new(vec![], &nfa, PrintOptions::default());
+        for input in &["x\n1\n", "a\nb\n", "x\n2\n"] {
+            let mut buffered = Printer::new(vec![], &nfa, PrintOptions::default());
+            searcher.search(input.as_bytes(), &mut buffered.sink("")).unwrap();
+            printer.append(buffered).unwrap();
+        }
+        assert_eq!(String::from_utf8(printer.into_inner()).unwrap(), "x\n1\n--\nx\n2\n");
+    }
+}
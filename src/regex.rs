@@ -0,0 +1,176 @@
+use super::matcher::{FindIter, Matcher};
+use super::nfa::NFA;
+use super::parser::{Flags, ParseError};
+
+/**
+ * A Regex is a regular expression compiled for searching text from code
+ * rather than from the command line. It is searched with the same NFA,
+ * or Backtrack matcher, that thegrep would search files with.
+ */
+pub struct Regex {
+    matcher: Box<dyn Matcher>,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, ParseError> {
+        Regex::with_flags(pattern, Flags::default())
+    }
+
+    pub fn with_flags(pattern: &str, flags: Flags) -> Result<Regex, ParseError> {
+        let nfa = NFA::with_flags(pattern, flags)?;
+        Ok(Regex {
+            matcher: nfa.into_matcher(),
+        })
+    }
+
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.matcher.is_match(haystack.as_bytes())
+    }
+
+    /**
+     * The successive non-overlapping matches in haystack, as the byte
+     * offsets of their starts and ends. An empty match can only be found
+     * between chars, never inside the encoding of one.
+     */
+    pub fn find_iter<'r, 'h>(&'r self, haystack: &'h str) -> impl Iterator<Item = (usize, usize)> + 'r
+    where
+        'h: 'r,
+    {
+        FindIter::new(&*self.matcher, haystack.as_bytes()).filter(move |(start, _)| haystack.is_char_boundary(*start))
+    }
+
+    /**
+     * A copy of haystack with every match in it replaced by the Template
+     * replacement, so that $1 is replaced with what group 1 matched.
+     */
+    pub fn replace_all(&self, haystack: &str, replacement: &str) -> String {
+        let template = Template::new(replacement);
+        let mut replaced = vec![];
+        let mut last = 0;
+        for (start, end) in self.find_iter(haystack) {
+            replaced.extend_from_slice(&haystack.as_bytes()[last..start]);
+            template.expand(&*self.matcher, haystack.as_bytes(), start, end, &mut replaced);
+            last = end;
+        }
+        replaced.extend_from_slice(&haystack.as_bytes()[last..]);
+        String::from_utf8(replaced).expect("matches of a str start and end between its chars")
+    }
+}
+
+/**
+ * A Template is the text a match is replaced with, as given to
+ * --replace. In it, $1 stands for what group 1 matched, $0 for the whole
+ * match, $name for what the group called name matched, and $$ for a '$'.
+ * A name runs as long as there are letters, digits and '_' after the '$',
+ * so braces can end it sooner, as in ${1}st. A group that does not exist
+ * or took no part in the match stands for nothing, and a '$' that is not
+ * followed by a name is itself.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    Text(String),
+    Group(usize),
+    Name(String),
+}
+
+impl Template {
+    pub fn new(template: &str) -> Template {
+        let mut pieces = vec![];
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(i) = rest.find('$') {
+            text.push_str(&rest[..i]);
+            rest = &rest[i + 1..];
+            if let Some(after) = rest.strip_prefix('$') {
+                text.push('$');
+                rest = after;
+                continue;
+            }
+            let (name, after) = match rest.strip_prefix('{') {
+                Some(braced) => braced.split_once('}').unwrap_or(("", rest)),
+                None => rest.split_at(rest.find(|c: char| c != '_' && !c.is_ascii_alphanumeric()).unwrap_or(rest.len())),
+            };
+            if name.is_empty() {
+                text.push('$');
+                continue;
+            }
+            if !text.is_empty() {
+                pieces.push(Piece::Text(std::mem::take(&mut text)));
+            }
+            pieces.push(match name.parse() {
+                Ok(index) => Piece::Group(index),
+                Err(_) => Piece::Name(String::from(name)),
+            });
+            rest = after;
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Template { pieces }
+    }
+
+    /**
+     * Append the template's text for the match from start to end in
+     * haystack, which matcher found, to out.
+     */
+    pub fn expand(&self, matcher: &dyn Matcher, haystack: &[u8], start: usize, end: usize, out: &mut Vec<u8>) {
+        let captures = matcher.captures(haystack, start, end);
+        for piece in &self.pieces {
+            let index = match piece {
+                Piece::Text(text) => {
+                    out.extend_from_slice(text.as_bytes());
+                    continue;
+                }
+                Piece::Group(index) => Some(*index),
+                Piece::Name(name) => matcher.group_index(name),
+            };
+            let slots = index.and_then(|i| captures.get(2 * i..2 * i + 2));
+            if let Some([Some(s), Some(e)]) = slots {
+                out.extend_from_slice(&haystack[*s..*e]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aho_corasick::AhoCorasick;
+
+    #[test]
+    fn templates() {
+        let text = |s: &str| Piece::Text(String::from(s));
+        let name = |s: &str| Piece::Name(String::from(s));
+        assert_eq!(Template::new("$1-$user").pieces, vec![Piece::Group(1), text("-"), name("user")]);
+        assert_eq!(Template::new("${1}st").pieces, vec![Piece::Group(1), text("st")]);
+        assert_eq!(Template::new("$1st").pieces, vec![name("1st")]);
+        assert_eq!(Template::new("$$5 $ ${} ${x").pieces, vec![text("$5 $ ${} ${x")]);
+    }
+
+    #[test]
+    fn replace_all() {
+        let regex = Regex::new("(?P<user>\\w+)@(\\w+)").unwrap();
+        assert_eq!(regex.replace_all("mail ann@unc or bo@duke", "$2:${user}"), "mail unc:ann or duke:bo");
+        assert_eq!(regex.replace_all("none here", "$1"), "none here");
+        let regex = Regex::new("a(b)?").unwrap();
+        assert_eq!(regex.replace_all("ab a", "[$1|$0|$9|$nope]"), "[b|ab||] [|a||]");
+        let regex = Regex::new("(\\w)\\1").unwrap();
+        assert_eq!(regex.replace_all("bookkeeper", "<$1>"), "b<o><k><e>per");
+        let regex = Regex::new("x*").unwrap();
+        assert_eq!(regex.replace_all("éa", "-"), "-é-a-");
+    }
+
+    #[test]
+    fn literal_groups() {
+        let ac = AhoCorasick::new(&["cat"]);
+        let mut out = vec![];
+        Template::new("[$0$1]").expand(&ac, b"a cat", 2, 5, &mut out);
+        assert_eq!(out, b"[cat]");
+    }
+}
@@ -0,0 +1,640 @@
+use super::matcher::Matcher;
+use memmap2::Mmap;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+/**
+ * A file is considered binary when it contains a NUL byte. Binary files
+ * can have a single message printed in place of their matching lines,
+ * be searched as text, or be skipped.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryFiles {
+    Binary,
+    Text,
+    WithoutMatch,
+}
+
+impl std::str::FromStr for BinaryFiles {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BinaryFiles, String> {
+        match s {
+            "binary" => Ok(BinaryFiles::Binary),
+            "text" => Ok(BinaryFiles::Text),
+            "without-match" => Ok(BinaryFiles::WithoutMatch),
+            _ => Err(format!("unknown binary-files type '{}'", s)),
+        }
+    }
+}
+
+/**
+ * Options that decide which lines of input are selected and when a
+ * search of the input stops.
+ * - invert selects the lines that do not match instead of those that do
+ * - max_count stops the search once that many lines have been selected
+ * - binary decides what happens to binary input
+ * - before_context and after_context are how many lines around each
+ *   selected line are passed on as context
+ */
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    pub invert: bool,
+    pub max_count: Option<u64>,
+    pub binary: BinaryFiles,
+    pub before_context: usize,
+    pub after_context: usize,
+}
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions {
+            invert: false,
+            max_count: None,
+            binary: BinaryFiles::Binary,
+            before_context: 0,
+            after_context: 0,
+        }
+    }
+}
+
+/**
+ * A line of input selected by a Searcher.
+ * - bytes is the line, without its line terminator
+ * - number is the line's number, counting from 1
+ * - offset is the byte offset of the start of the line in the input
+ * - binary is set once the input has been found to be binary
+ */
+#[derive(Debug)]
+pub struct Line<'b> {
+    pub bytes: &'b [u8],
+    pub number: u64,
+    pub offset: u64,
+    pub binary: bool,
+}
+
+/**
+ * A Sink receives the lines a Searcher selects. Keeping what to do with
+ * selected lines out of the Searcher lets printing lines, counting them
+ * and listing files all share one search loop.
+ */
+pub trait Sink {
+    /**
+     * Called with each selected line, in order. Returns whether the
+     * Searcher should keep going, so sinks that only need to know
+     * whether anything was selected can stop the search early.
+     */
+    fn matched(&mut self, line: &Line) -> io::Result<bool>;
+
+    /**
+     * Called with each line of context around the selected lines, in
+     * order with them.
+     */
+    fn context(&mut self, _line: &Line) -> io::Result<()> {
+        Ok(())
+    }
+
+    /**
+     * Called ahead of each group of selected and context lines that does
+     * not follow on from the lines before it.
+     */
+    fn context_break(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /**
+     * Called once the search is over with the number of selected lines.
+     */
+    fn finish(&mut self, matches: u64) -> io::Result<()>;
+}
+
+/**
+ * Files at least this big are memory mapped rather than read into a
+ * buffer of their own.
+ */
+const MMAP_THRESHOLD: u64 = 1 << 20;
+
+/**
+ * The capacity of the buffer input that cannot be read all at once is
+ * read through, which is also how much is checked for a NUL byte up front.
+ */
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/**
+ * A Searcher reads input line by line, selects lines with a Matcher and
+ * hands them to a Sink.
+ */
+pub struct Searcher<'m> {
+    matcher: &'m dyn Matcher,
+    options: SearchOptions,
+}
+
+impl<'m> Searcher<'m> {
+    pub fn new(matcher: &'m dyn Matcher, options: SearchOptions) -> Searcher<'m> {
+        Searcher { matcher, options }
+    }
+
+    pub fn matcher(&self) -> &'m dyn Matcher {
+        self.matcher
+    }
+
+    /**
+     * Search the file at path, returning the number of lines selected.
+     * A regular file is searched in memory, mapped if it is big and read
+     * into a buffer otherwise, and anything else, like a pipe, is read
+     * through a buffer a line at a time.
+     */
+    pub fn search_path<S: Sink>(&self, path: &Path, sink: &mut S) -> io::Result<u64> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return self.search(BufReader::with_capacity(BUFFER_SIZE, file), sink);
+        }
+        if metadata.len() >= MMAP_THRESHOLD {
+            // the map is only read from. Should another process truncate the file while it is
+            // searched we may be killed by SIGBUS, which is the usual price of mapping files
+            if let Ok(map) = unsafe { Mmap::map(&file) } {
+                return self.search_slice(&map, sink);
+            }
+        }
+        let mut buffer = Vec::with_capacity(metadata.len() as usize);
+        file.read_to_end(&mut buffer)?;
+        self.search_slice(&buffer, sink)
+    }
+
+    /**
+     * Search reader, returning the number of lines selected. Lines are
+     * read as raw bytes so that text in other encodings is still
+     * searched. Input is binary once a NUL byte turns up in the first
+     * buffer read or in a line that is searched.
+     */
+    pub fn search<R: BufRead, S: Sink>(&self, mut reader: R, sink: &mut S) -> io::Result<u64> {
+        if self.options.max_count == Some(0) {
+            return self.finish(0, sink);
+        }
+        let mut progress = Progress::new(&self.options, reader.fill_buf()?);
+        let mut line = Vec::new();
+        let mut number = 0;
+        let mut offset = 0;
+        while !progress.done {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+            if read == 0 {
+                break;
+            }
+            number += 1;
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            self.line(&mut progress, &line, number, offset, sink)?;
+            offset += read as u64;
+        }
+        self.finish(progress.matches, sink)
+    }
+
+    /**
+     * Search input that is all in memory, returning the number of lines
+     * selected just as search would. Rather than trying every line, the
+     * Matcher looks for the next candidate for a match in the rest of the
+     * input and only the line it is in, along with the lines of before
+     * context ahead of it, are split off and searched. Lines in between are skipped over
+     * after being counted and checked for NUL bytes. A Matcher with no
+     * cheap way to find candidates makes every line one, which leaves the
+     * lines to be searched one at a time as search does.
+     */
+    pub fn search_slice<S: Sink>(&self, haystack: &[u8], sink: &mut S) -> io::Result<u64> {
+        if self.options.max_count == Some(0) {
+            return self.finish(0, sink);
+        }
+        let mut progress = Progress::new(&self.options, &haystack[..haystack.len().min(BUFFER_SIZE)]);
+        let mut pos = 0;
+        let mut number = 0;
+        while !progress.done && pos < haystack.len() {
+            //inverted searches and after context need every line, so only then are none skipped
+            let mut until = pos;
+            if !self.options.invert && progress.after == 0 {
+                let start = match self.matcher.candidate(haystack, pos) {
+                    Some(start) => start,
+                    None => {
+                        progress.skip(&self.options, &haystack[pos..]);
+                        break;
+                    }
+                };
+                until = line_start(haystack, pos, start);
+                let keep = (0..self.options.before_context)
+                    .try_fold(until, |at, _| (at > pos).then(|| line_start(haystack, pos, at - 1)))
+                    .unwrap_or(pos);
+                if keep > pos {
+                    progress.skip(&self.options, &haystack[pos..keep]);
+                    number += haystack[pos..keep].iter().filter(|b| **b == b'\n').count() as u64;
+                    pos = keep;
+                }
+            }
+            while !progress.done && pos <= until && pos < haystack.len() {
+                let end = haystack[pos..]
+                    .iter()
+                    .position(|b| *b == b'\n')
+                    .map_or(haystack.len(), |i| pos + i);
+                number += 1;
+                self.line(&mut progress, &haystack[pos..end], number, pos as u64, sink)?;
+                pos = end + 1;
+            }
+        }
+        self.finish(progress.matches, sink)
+    }
+
+    /**
+     * Search one line, passing it to the sink if it is selected or is
+     * context. Lines before a selected line are kept in a ring buffer
+     * until it is known whether they are context, and the after context
+     * of a line that reaches max_count is still passed on.
+     */
+    fn line<S: Sink>(
+        &self,
+        progress: &mut Progress,
+        bytes: &[u8],
+        number: u64,
+        offset: u64,
+        sink: &mut S,
+    ) -> io::Result<()> {
+        if progress.skip(&self.options, bytes) {
+            return Ok(());
+        }
+        let binary = progress.binary;
+        let line = Line {
+            bytes,
+            number,
+            offset,
+            binary,
+        };
+        if progress.selecting && self.matcher.is_match(bytes) != self.options.invert {
+            let first = progress.before.front().map_or(number, |b| b.number);
+            if self.has_context() && progress.last.is_none_or(|last| last + 1 < first) {
+                sink.context_break()?;
+            }
+            for b in progress.before.drain(..) {
+                sink.context(&Line {
+                    bytes: &b.bytes,
+                    number: b.number,
+                    offset: b.offset,
+                    binary,
+                })?;
+            }
+            progress.matches += 1;
+            progress.last = Some(number);
+            progress.after = self.options.after_context;
+            progress.selecting = Some(progress.matches) != self.options.max_count;
+            progress.done = !sink.matched(&line)?;
+        } else if progress.after > 0 {
+            sink.context(&line)?;
+            progress.last = Some(number);
+            progress.after -= 1;
+        } else if self.options.before_context > 0 {
+            if progress.before.len() == self.options.before_context {
+                progress.before.pop_front();
+            }
+            progress.before.push_back(Buffered {
+                bytes: bytes.to_vec(),
+                number,
+                offset,
+            });
+        }
+        progress.done |= !progress.selecting && progress.after == 0;
+        Ok(())
+    }
+
+    fn finish<S: Sink>(&self, matches: u64, sink: &mut S) -> io::Result<u64> {
+        sink.finish(matches)?;
+        Ok(matches)
+    }
+
+    fn has_context(&self) -> bool {
+        self.options.before_context > 0 || self.options.after_context > 0
+    }
+}
+
+/**
+ * The offset of the start of the line that the byte at offset at is in,
+ * looking no further back than from.
+ */
+fn line_start(haystack: &[u8], from: usize, at: usize) -> usize {
+    haystack[from..at]
+        .iter()
+        .rposition(|b| *b == b'\n')
+        .map_or(from, |i| from + i + 1)
+}
+
+/**
+ * How far a search has got.
+ * - matches is the number of lines selected so far
+ * - binary is set once the input has been found to be binary
+ * - before holds the lines that may turn out to be before context
+ * - after is how many more lines are after context
+ * - last is the number of the last line passed on to the sink
+ * - selecting is cleared once max_count lines have been selected
+ * - done is set once nothing more is to be passed on
+ */
+struct Progress {
+    matches: u64,
+    binary: bool,
+    before: VecDeque<Buffered>,
+    after: usize,
+    last: Option<u64>,
+    selecting: bool,
+    done: bool,
+}
+
+impl Progress {
+    /**
+     * Start a search, given the first of its input to look for NUL bytes in.
+     */
+    fn new(options: &SearchOptions, first: &[u8]) -> Progress {
+        let mut progress = Progress {
+            matches: 0,
+            binary: false,
+            before: VecDeque::with_capacity(options.before_context),
+            after: 0,
+            last: None,
+            selecting: true,
+            done: false,
+        };
+        progress.skip(options, first);
+        progress
+    }
+
+    /**
+     * Look over input that is not selected from for NUL bytes. Binary
+     * input that is to be treated as without a match ends the search with
+     * nothing selected, which is returned.
+     */
+    fn skip(&mut self, options: &SearchOptions, bytes: &[u8]) -> bool {
+        self.binary |= options.binary != BinaryFiles::Text && bytes.contains(&0);
+        if self.binary && options.binary == BinaryFiles::WithoutMatch {
+            self.matches = 0;
+            self.done = true;
+        }
+        self.done
+    }
+}
+
+/**
+ * A line held on to in case it turns out to be before context.
+ */
+struct Buffered {
+    bytes: Vec<u8>,
+    number: u64,
+    offset: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfa::NFA;
+
+    /**
+     * A Sink that collects the lines it is given.
+     */
+    #[derive(Debug, Default, PartialEq)]
+    struct Collect {
+        lines: Vec<String>,
+        context: Vec<String>,
+        positions: Vec<(u64, u64)>,
+        binary: bool,
+        finished: Option<u64>,
+        stop_after: Option<usize>,
+    }
+
+    impl Sink for Collect {
+        fn matched(&mut self, line: &Line) -> io::Result<bool> {
+            self.lines.push(String::from_utf8_lossy(line.bytes).into_owned());
+            self.positions.push((line.number, line.offset));
+            self.binary |= line.binary;
+            Ok(Some(self.lines.len()) != self.stop_after)
+        }
+
+        fn context(&mut self, line: &Line) -> io::Result<()> {
+            self.context.push(format!("{}-{}", line.number, String::from_utf8_lossy(line.bytes)));
+            Ok(())
+        }
+
+        fn context_break(&mut self) -> io::Result<()> {
+            self.context.push(String::from("--"));
+            Ok(())
+        }
+
+        fn finish(&mut self, matches: u64) -> io::Result<()> {
+            self.finished = Some(matches);
+            Ok(())
+        }
+    }
+
+    //searches input both a line at a time and all in memory, which must agree
+    fn search(pattern: &str, options: SearchOptions, input: &[u8]) -> Collect {
+        let nfa = NFA::from(pattern).unwrap();
+        let searcher = Searcher::new(&nfa, options);
+        let mut sink = Collect::default();
+        searcher.search(input, &mut sink).unwrap();
+        let mut sliced = Collect::default();
+        searcher.search_slice(input, &mut sliced).unwrap();
+        assert_eq!(sink, sliced);
+        sink
+    }
+
+    #[test]
+    fn selects_matching_lines() {
+        let sink = search("b+", SearchOptions::default(), b"abc\nxyz\nbb");
+        assert_eq!(sink.lines, vec!["abc", "bb"]);
+        assert_eq!(sink.finished, Some(2));
+        assert!(!sink.binary);
+    }
+
+    #[test]
+    fn positions() {
+        let sink = search("b", SearchOptions::default(), "b\ncafé\nab\n\nb".as_bytes());
+        assert_eq!(sink.positions, vec![(1, 0), (3, 8), (5, 12)]);
+    }
+
+    #[test]
+    fn invert() {
+        let options = SearchOptions {
+            invert: true,
+            ..SearchOptions::default()
+        };
+        let sink = search("b", options, b"abc\nxyz\nbb\n\n");
+        assert_eq!(sink.lines, vec!["xyz", ""]);
+        assert_eq!(sink.finished, Some(2));
+    }
+
+    #[test]
+    fn max_count() {
+        let options = SearchOptions {
+            max_count: Some(2),
+            ..SearchOptions::default()
+        };
+        let sink = search("a", options.clone(), b"a1\nb\na2\na3\n");
+        assert_eq!(sink.lines, vec!["a1", "a2"]);
+        assert_eq!(sink.finished, Some(2));
+        let options = SearchOptions {
+            max_count: Some(0),
+            ..options
+        };
+        let sink = search("a", options, b"a1\n");
+        assert!(sink.lines.is_empty());
+        assert_eq!(sink.finished, Some(0));
+    }
+
+    #[test]
+    fn sink_stops_search() {
+        let nfa = NFA::from("a").unwrap();
+        let mut sink = Collect {
+            stop_after: Some(1),
+            ..Collect::default()
+        };
+        let matches = Searcher::new(&nfa, SearchOptions::default())
+            .search(&b"a1\na2\n"[..], &mut sink)
+            .unwrap();
+        assert_eq!(matches, 1);
+        assert_eq!(sink.lines, vec!["a1"]);
+    }
+
+    #[test]
+    fn binary() {
+        let sink = search("a", SearchOptions::default(), b"a\0\na\n");
+        assert!(sink.binary);
+        assert_eq!(sink.finished, Some(2));
+        let options = SearchOptions {
+            binary: BinaryFiles::Text,
+            ..SearchOptions::default()
+        };
+        let sink = search("a", options, b"a\0\na\n");
+        assert!(!sink.binary);
+        let options = SearchOptions {
+            binary: BinaryFiles::WithoutMatch,
+            ..SearchOptions::default()
+        };
+        let sink = search("a", options, b"a\0\na\n");
+        assert!(sink.lines.is_empty());
+        assert_eq!(sink.finished, Some(0));
+    }
+
+    fn context(before_context: usize, after_context: usize) -> SearchOptions {
+        SearchOptions {
+            before_context,
+            after_context,
+            ..SearchOptions::default()
+        }
+    }
+
+    const LOG: &[u8] = b"a\nb\nx\nc\nd\ne\nf\nx\ng\nx\nh\n";
+
+    #[test]
+    fn before_context() {
+        let sink = search("x", context(2, 0), LOG);
+        assert_eq!(sink.context, vec!["--", "1-a", "2-b", "--", "6-e", "7-f", "9-g"]);
+        assert_eq!(sink.finished, Some(3));
+    }
+
+    #[test]
+    fn after_context() {
+        let sink = search("x", context(0, 1), LOG);
+        assert_eq!(sink.context, vec!["--", "4-c", "--", "9-g", "11-h"]);
+    }
+
+    #[test]
+    fn overlapping_context() {
+        let sink = search("x", context(1, 1), LOG);
+        assert_eq!(sink.context, vec!["--", "2-b", "4-c", "--", "7-f", "9-g", "11-h"]);
+        let sink = search("x", context(3, 3), LOG);
+        assert_eq!(sink.context.iter().filter(|l| *l == "--").count(), 1);
+        assert_eq!(sink.context.len(), 9);
+    }
+
+    #[test]
+    fn context_after_max_count() {
+        let options = SearchOptions {
+            max_count: Some(1),
+            ..context(0, 2)
+        };
+        let sink = search("x", options, LOG);
+        assert_eq!(sink.lines, vec!["x"]);
+        assert_eq!(sink.context, vec!["--", "4-c", "5-d"]);
+    }
+
+    #[test]
+    fn matches_across_lines_are_not_selected() {
+        let sink = search("a.b", SearchOptions::default(), b"a\nb\nxa\nbaxb\n");
+        assert_eq!(sink.lines, vec!["baxb"]);
+        assert_eq!(sink.positions, vec![(4, 7)]);
+    }
+
+    //a pattern that can match newlines and has no text every match contains must still be
+    //searched a line at a time, not from every line to the end of the input
+    #[test]
+    fn patterns_across_lines_without_literals() {
+        let mut input = b"the lazy dog\n".repeat(2000);
+        input.extend(b"end q\n");
+        let sink = search("\\D*[qr]", SearchOptions::default(), &input);
+        assert_eq!(sink.lines, vec!["end q"]);
+        assert_eq!(sink.positions, vec![(2001, 2000 * 13)]);
+        let sink = search("\\s", SearchOptions::default(), b"a\nb c\nd");
+        assert_eq!(sink.lines, vec!["b c"]);
+    }
+
+    //the NUL is past what is checked up front and in no line that is searched in memory
+    #[test]
+    fn binary_past_last_match() {
+        let nfa = NFA::from("a").unwrap();
+        let options = SearchOptions {
+            binary: BinaryFiles::WithoutMatch,
+            ..SearchOptions::default()
+        };
+        let mut input = b"a\n".to_vec();
+        input.extend(vec![b'b'; BUFFER_SIZE]);
+        input.extend(b"\0\n");
+        let mut sink = Collect::default();
+        let matches = Searcher::new(&nfa, options).search_slice(&input, &mut sink).unwrap();
+        assert_eq!(matches, 0);
+        assert_eq!(sink.finished, Some(0));
+    }
+
+    //a backtracking search for a candidate across lines used to give up, which skipped the rest
+    //of a file but not the same lines on stdin
+    #[test]
+    fn backtracking_path_and_stdin_agree() {
+        let path = std::env::temp_dir().join(format!("thegrep-backtrack-{}", std::process::id()));
+        let mut text = b"aaaaaaaaaa\n".repeat(40);
+        text.extend(b"xQx\n");
+        std::fs::write(&path, &text).unwrap();
+        let matcher = NFA::from("(a|\\D)*[QR]\\1").unwrap().into_matcher();
+        let searcher = Searcher::new(&*matcher, SearchOptions::default());
+        let mut from_path = Collect::default();
+        let result = searcher.search_path(&path, &mut from_path);
+        std::fs::remove_file(&path).unwrap();
+        let mut from_stdin = Collect::default();
+        searcher.search(&text[..], &mut from_stdin).unwrap();
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(from_path, from_stdin);
+        assert_eq!(from_path.lines, vec!["xQx"]);
+        assert!(!matcher.gave_up());
+    }
+
+    #[test]
+    fn search_path() {
+        let path = std::env::temp_dir().join(format!("thegrep-mmap-{}", std::process::id()));
+        let line = b"just another line of text\n";
+        let mut text = line.repeat(MMAP_THRESHOLD as usize / line.len());
+        text.extend(b"the needle\n");
+        text.extend(line.repeat(10));
+        std::fs::write(&path, &text).unwrap();
+        let nfa = NFA::from("needle").unwrap();
+        let mut sink = Collect::default();
+        let result = Searcher::new(&nfa, SearchOptions::default()).search_path(&path, &mut sink);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(sink.lines, vec!["the needle"]);
+        let number = MMAP_THRESHOLD / line.len() as u64 + 1;
+        assert_eq!(sink.positions, vec![(number, (number - 1) * line.len() as u64)]);
+    }
+}
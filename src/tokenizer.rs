@@ -1,3 +1,5 @@
+use super::nfa::unicode_tables;
+use std::fmt;
 use std::iter::Peekable;
 use std::str::Chars;
 
@@ -13,39 +15,385 @@ pub enum Token {
     AnyChar,
     Char(char),
     KleenePlus,
+    Question,
+    LazyStar,
+    LazyPlus,
+    LazyQuestion,
+    Flags(FlagChange),
+    Assertion(Assertion),
+    Class(Class),
+    Bracket(Bracket),
+    Backref(usize),
+    Look(Look),
+    NamedGroup(String),
+    NonCapturing(FlagChange),
+    Error(String),
+}
+
+/**
+ * A Class is a shorthand for a set of chars, like \d for digits. Its
+ * negation, written in upper case, is every char not in the set.
+ * - Digit, \d, is the ASCII digits 0 to 9
+ * - Word, \w, is the word characters \b looks for: letters and digits
+ *   in any script, and '_'
+ * - Space, \s, is the Unicode white space characters
+ * - Property, \p{Greek} or \pL, is a Unicode general category or script,
+ *   and \P{Greek} is its negation
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Class {
+    pub kind: ClassKind,
+    pub negated: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClassKind {
+    Digit,
+    Word,
+    Space,
+    Property(Property),
+}
+
+/**
+ * A Property is a Unicode general category like L or Nd, or a script like
+ * Greek, under the name the tables give it, along with the ranges of
+ * chars that have it. It debugs as just its name.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub struct Property {
+    pub name: &'static str,
+    pub ranges: &'static [(char, char)],
+}
+
+impl fmt::Debug for Property {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Property({:?})", self.name)
+    }
+}
+
+/**
+ * A Bracket expression like [a-z_] matches any one char that one of its
+ * items does, or with a ^ after the [, any char that none of them do. A ]
+ * right after the [ or [^ is one of the items rather than the end, and a
+ * - at either end of the items is taken literally.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bracket {
+    pub negated: bool,
+    pub items: Vec<BracketItem>,
+}
+
+/**
+ * The items in a bracket expression are ranges of chars, with a single
+ * char being a range of one, shorthand classes like \d, and POSIX classes
+ * like [:alpha:].
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum BracketItem {
+    Range(char, char),
+    Class(Class),
+    Posix(PosixClass),
+}
+
+/**
+ * The POSIX character classes. The classes of letters and of white space
+ * (alpha, alnum, upper, lower and space) follow Unicode, so [[:alpha:]]
+ * matches é and [[:space:]] a no-break space. The rest are ASCII, as in
+ * the C locale: digit and xdigit are only ever ASCII digits, and punct,
+ * graph, print, cntrl and blank only match ASCII chars. alnum is alpha
+ * along with the ASCII digits.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PosixClass {
+    Alnum,
+    Alpha,
+    Blank,
+    Cntrl,
+    Digit,
+    Graph,
+    Lower,
+    Print,
+    Punct,
+    Space,
+    Upper,
+    Xdigit,
+}
+
+const POSIX_CLASSES: &[(&str, PosixClass)] = &[
+    ("alnum", PosixClass::Alnum),
+    ("alpha", PosixClass::Alpha),
+    ("blank", PosixClass::Blank),
+    ("cntrl", PosixClass::Cntrl),
+    ("digit", PosixClass::Digit),
+    ("graph", PosixClass::Graph),
+    ("lower", PosixClass::Lower),
+    ("print", PosixClass::Print),
+    ("punct", PosixClass::Punct),
+    ("space", PosixClass::Space),
+    ("upper", PosixClass::Upper),
+    ("xdigit", PosixClass::Xdigit),
+];
+
+impl PosixClass {
+    pub fn from_name(name: &str) -> Option<PosixClass> {
+        POSIX_CLASSES.iter().find(|(n, _)| *n == name).map(|(_, class)| *class)
+    }
+
+    pub fn name(self) -> &'static str {
+        POSIX_CLASSES.iter().find(|(_, class)| *class == self).map(|(n, _)| *n).unwrap()
+    }
+}
+
+/**
+ * Assertions match the empty string, but only where the input around
+ * it is a certain way.
+ * - WordBoundary, \b, is between a word character and anything else
+ * - NotWordBoundary, \B, is anywhere \b is not
+ *
+ * The start and end of the input count as non-word characters.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Assertion {
+    WordBoundary,
+    NotWordBoundary,
+}
+
+/**
+ * A Look opens a lookaround group, which matches the empty string where
+ * what is in it matches the text right after it, like (?=x), or with
+ * behind, right before it, like (?<=x). A negated one, like (?!x) or
+ * (?<!x), matches where what is in it does not.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Look {
+    pub behind: bool,
+    pub negated: bool,
+}
+
+/**
+ * The flags an inline flag group like (?i) or (?-i) turns on or off, or
+ * a non-capturing group like (?i:x) turns on or off inside it. Flags the
+ * group does not mention are None and left as they were.
+ * - case_insensitive, i, matches letters in any case
+ * - dot_all, s, lets '.' match '\n' too
+ * - extended, x, ignores whitespace and comments from '#' to the end of
+ *   the line, outside of bracket expressions
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FlagChange {
+    pub case_insensitive: Option<bool>,
+    pub dot_all: Option<bool>,
+    pub extended: Option<bool>,
+}
+
+/**
+ * A FlagChange displays as the flags it turns on, then a '-' and the
+ * flags it turns off, as in is-x.
+ */
+impl fmt::Display for FlagChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags = [('i', self.case_insensitive), ('s', self.dot_all), ('x', self.extended)];
+        for (flag, _) in flags.iter().filter(|(_, on)| *on == Some(true)) {
+            write!(f, "{}", flag)?;
+        }
+        if flags.iter().any(|(_, on)| *on == Some(false)) {
+            write!(f, "-")?;
+        }
+        for (flag, _) in flags.iter().filter(|(_, on)| *on == Some(false)) {
+            write!(f, "{}", flag)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * A Span is the half-open range of character columns, counted from 0,
+ * that a token was lexed from in the pattern.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/**
+ * A Spanned token pairs a Token with the Span it came from so that
+ * errors further down the pipeline can point back into the pattern.
+ */
+#[derive(Debug, PartialEq)]
+pub struct Spanned {
+    pub token: Token,
+    pub span: Span,
 }
 
 pub struct Tokenizer<'str> {
     chars: Peekable<Chars<'str>>,
+    pos: usize,
+    // column the token being lexed starts at, which an Error token can move up to the
+    // character that is actually at fault
+    start: usize,
+    // whether extended mode is on in each group the next token is in, innermost last
+    extended: Vec<bool>,
 }
 
 impl<'str> Tokenizer<'str> {
-    pub fn new(input: &'str str) -> Tokenizer {
+    pub fn new(input: &'str str) -> Tokenizer<'str> {
         Tokenizer {
             chars: input.chars().peekable(),
+            pos: 0,
+            start: 0,
+            extended: vec![false],
         }
     }
+
+    /**
+     * The column the next token will start at, which is also the column
+     * just past the end of the input once the Tokenizer is exhausted.
+     */
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
 }
 
 impl<'str> Iterator for Tokenizer<'str> {
-    type Item = Token;
+    type Item = Spanned;
     /**
      * The 'next' method returns the next
      * complete token in the Tokenizer's
-     * input string or None at all
+     * input string along with its Span,
+     * or None at all
      */
-    fn next(&mut self) -> Option<Token> {
-        if let Some(c) = self.chars.peek() {
-            Some(match c {
-                '(' | ')' => self.lex_paren(),
-                '|' => self.lex_union(),
-                '*' => self.lex_kleene(),
-                '.' => self.lex_anychar(),
-                '+' => self.lex_kleeneplus(),
-                _ => self.lex_char(),
-            })
+    fn next(&mut self) -> Option<Spanned> {
+        self.skip_extended();
+        self.start = self.pos;
+        let c = *self.chars.peek()?;
+        let token = match c {
+            '(' if self.second() == Some('?') => match self.lex_look().or_else(|| self.lex_named()) {
+                Some(token) => token,
+                None => self.lex_flags(),
+            },
+            '(' | ')' => self.lex_paren(),
+            '|' => self.lex_union(),
+            '*' => self.lex_kleene(),
+            '.' => self.lex_anychar(),
+            '+' => self.lex_kleeneplus(),
+            '?' => self.lex_question(),
+            '\\' => self.lex_escape(),
+            '[' => self.lex_bracket(),
+            _ => self.lex_char(),
+        };
+        self.scope(&token);
+        Some(Spanned {
+            token,
+            span: Span {
+                start: self.start,
+                end: self.pos,
+            },
+        })
+    }
+}
+
+/**
+ * The token for the escape of c. \1 to \9 refer back to the text the
+ * groups with those numbers matched. Other letters and digits after a
+ * backslash are reserved for escapes with meanings of their own, and
+ * any other char is taken literally, which is how metacharacters like
+ * \* and \( are matched.
+ */
+fn escape(c: char) -> Token {
+    match c {
+        'b' => Token::Assertion(Assertion::WordBoundary),
+        'B' => Token::Assertion(Assertion::NotWordBoundary),
+        'd' | 'D' | 'w' | 'W' | 's' | 'S' => Token::Class(Class {
+            kind: match c.to_ascii_lowercase() {
+                'd' => ClassKind::Digit,
+                'w' => ClassKind::Word,
+                _ => ClassKind::Space,
+            },
+            negated: c.is_uppercase(),
+        }),
+        '1'..='9' => Token::Backref(c as usize - '0' as usize),
+        'n' => Token::Char('\n'),
+        't' => Token::Char('\t'),
+        c if c.is_alphanumeric() => Token::Error(format!("unknown escape '\\{}'", c)),
+        c => Token::Char(c),
+    }
+}
+
+/**
+ * Tokens display as the pattern text they were lexed from, which is
+ * how they are quoted in error messages.
+ */
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::UnionBar => write!(f, "|"),
+            Token::KleeneStar => write!(f, "*"),
+            Token::AnyChar => write!(f, "."),
+            Token::Char(c) if c.is_control() => write!(f, "{}", c.escape_default()),
+            Token::Char(c) => write!(f, "{}", c),
+            Token::KleenePlus => write!(f, "+"),
+            Token::Question => write!(f, "?"),
+            Token::LazyStar => write!(f, "*?"),
+            Token::LazyPlus => write!(f, "+?"),
+            Token::LazyQuestion => write!(f, "??"),
+            Token::Flags(change) => write!(f, "(?{})", change),
+            Token::NonCapturing(change) => write!(f, "(?{}:", change),
+            Token::Assertion(assertion) => write!(f, "{}", assertion),
+            Token::Class(class) => write!(f, "{}", class),
+            Token::Bracket(bracket) => {
+                write!(f, "[")?;
+                if bracket.negated {
+                    write!(f, "^")?;
+                }
+                for item in &bracket.items {
+                    match item {
+                        BracketItem::Range(lo, hi) if lo == hi => write!(f, "{}", lo)?,
+                        BracketItem::Range(lo, hi) => write!(f, "{}-{}", lo, hi)?,
+                        BracketItem::Class(class) => write!(f, "{}", class)?,
+                        BracketItem::Posix(class) => write!(f, "[:{}:]", class.name())?,
+                    }
+                }
+                write!(f, "]")
+            }
+            Token::Backref(group) => write!(f, "\\{}", group),
+            Token::Look(look) => {
+                write!(f, "(?")?;
+                if look.behind {
+                    write!(f, "<")?;
+                }
+                write!(f, "{}", if look.negated { '!' } else { '=' })
+            }
+            Token::NamedGroup(name) => write!(f, "(?P<{}>", name),
+            Token::Error(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let c = match self.kind {
+            ClassKind::Digit => 'd',
+            ClassKind::Word => 'w',
+            ClassKind::Space => 's',
+            ClassKind::Property(property) if self.negated => return write!(f, "\\P{{{}}}", property.name),
+            ClassKind::Property(property) => return write!(f, "\\p{{{}}}", property.name),
+        };
+        if self.negated {
+            write!(f, "\\{}", c.to_ascii_uppercase())
         } else {
-            None
+            write!(f, "\\{}", c)
+        }
+    }
+}
+
+impl fmt::Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Assertion::WordBoundary => write!(f, "\\b"),
+            Assertion::NotWordBoundary => write!(f, "\\B"),
         }
     }
 }
@@ -53,7 +401,6 @@ impl<'str> Iterator for Tokenizer<'str> {
 /**
  * Unit tests for 'next' method
  */
-
 #[cfg(test)]
 mod iterator {
     use super::*;
@@ -61,65 +408,392 @@ mod iterator {
     #[test]
     fn empty() {
         let mut tokens = Tokenizer::new("");
-        assert_eq!(tokens.next(), None);
-        assert_eq!(tokens.next(), None);
+        assert_eq!(tokens.next().map(|t| t.token), None);
+        assert_eq!(tokens.next().map(|t| t.token), None);
     }
 
     #[test]
     fn single_char() {
         let mut tokens = Tokenizer::new("a");
-        assert_eq!(tokens.next(), Some(Token::Char('a')));
-        assert_eq!(tokens.next(), None);
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::Char('a')));
+        assert_eq!(tokens.next().map(|t| t.token), None);
     }
 
     #[test]
     fn parens() {
         let mut tokens = Tokenizer::new("(a)");
-        assert_eq!(tokens.next(), Some(Token::LParen));
-        assert_eq!(tokens.next(), Some(Token::Char('a')));
-        assert_eq!(tokens.next(), Some(Token::RParen));
-        assert_eq!(tokens.next(), None);
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::LParen));
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::Char('a')));
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::RParen));
+        assert_eq!(tokens.next().map(|t| t.token), None);
     }
 
     #[test]
     fn any_char() {
         let mut tokens = Tokenizer::new(".");
-        assert_eq!(tokens.next(), Some(Token::AnyChar));
-        assert_eq!(tokens.next(), None);
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::AnyChar));
+        assert_eq!(tokens.next().map(|t| t.token), None);
     }
 
     #[test]
     fn union_bar() {
         let mut tokens = Tokenizer::new("a|b");
-        assert_eq!(tokens.next(), Some(Token::Char('a')));
-        assert_eq!(tokens.next(), Some(Token::UnionBar));
-        assert_eq!(tokens.next(), Some(Token::Char('b')));
-        assert_eq!(tokens.next(), None);
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::Char('a')));
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::UnionBar));
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::Char('b')));
+        assert_eq!(tokens.next().map(|t| t.token), None);
     }
 
     #[test]
     fn kleene_star() {
         let mut tokens = Tokenizer::new("a*");
-        assert_eq!(tokens.next(), Some(Token::Char('a')));
-        assert_eq!(tokens.next(), Some(Token::KleeneStar));
-        assert_eq!(tokens.next(), None);
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::Char('a')));
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::KleeneStar));
+        assert_eq!(tokens.next().map(|t| t.token), None);
     }
 
     #[test]
     fn kleene_plus() {
         let mut tokens = Tokenizer::new("a+");
-        assert_eq!(tokens.next(), Some(Token::Char('a')));
-        assert_eq!(tokens.next(), Some(Token::KleenePlus));
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::Char('a')));
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::KleenePlus));
+        assert_eq!(tokens.next().map(|t| t.token), None);
+    }
+
+    #[test]
+    fn spans() {
+        let mut tokens = Tokenizer::new("(é|b)*");
+        assert_eq!(tokens.next().map(|t| t.span), Some(Span { start: 0, end: 1 }));
+        assert_eq!(tokens.next().map(|t| t.span), Some(Span { start: 1, end: 2 }));
+        assert_eq!(tokens.next().map(|t| t.span), Some(Span { start: 2, end: 3 }));
+        assert_eq!(tokens.next().map(|t| t.span), Some(Span { start: 3, end: 4 }));
+        assert_eq!(tokens.next().map(|t| t.span), Some(Span { start: 4, end: 5 }));
+        assert_eq!(tokens.pos(), 5);
+        assert_eq!(
+            tokens.next(),
+            Some(Spanned {
+                token: Token::KleeneStar,
+                span: Span { start: 5, end: 6 }
+            })
+        );
         assert_eq!(tokens.next(), None);
+        assert_eq!(tokens.pos(), 6);
+    }
+
+    #[test]
+    fn flags() {
+        let mut tokens = Tokenizer::new("(?i)a(?-i)(a)");
+        let on = FlagChange {
+            case_insensitive: Some(true),
+            ..FlagChange::default()
+        };
+        let off = FlagChange {
+            case_insensitive: Some(false),
+            ..FlagChange::default()
+        };
+        assert_eq!(
+            tokens.next(),
+            Some(Spanned {
+                token: Token::Flags(on),
+                span: Span { start: 0, end: 4 }
+            })
+        );
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::Char('a')));
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::Flags(off)));
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::LParen));
+        assert_eq!(Token::Flags(on).to_string(), "(?i)");
+        assert_eq!(Token::Flags(off).to_string(), "(?-i)");
+    }
+
+    #[test]
+    fn non_capturing() {
+        let tokens: Vec<_> = Tokenizer::new("(?:a)(?s-ix:b)").map(|t| t.token).collect();
+        let change = FlagChange {
+            case_insensitive: Some(false),
+            dot_all: Some(true),
+            extended: Some(false),
+        };
+        assert_eq!(
+            tokens,
+            vec![
+                Token::NonCapturing(FlagChange::default()),
+                Token::Char('a'),
+                Token::RParen,
+                Token::NonCapturing(change),
+                Token::Char('b'),
+                Token::RParen,
+            ]
+        );
+        assert_eq!(Token::NonCapturing(change).to_string(), "(?s-ix:");
+        assert_eq!(Token::NonCapturing(FlagChange::default()).to_string(), "(?:");
+        assert_eq!(
+            Tokenizer::new("(?-:a)").next().map(|t| t.token),
+            Some(Token::Error(String::from("expected a flag")))
+        );
+    }
+
+    #[test]
+    fn extended() {
+        let tokens: Vec<_> = Tokenizer::new("(?x) a b # c\n\\ [ ]").map(|t| t.token).collect();
+        assert_eq!(tokens[1..4], [Token::Char('a'), Token::Char('b'), Token::Char(' ')]);
+        assert_eq!(tokens.len(), 5);
+        let spans: Vec<_> = Tokenizer::new("(?x)  a").map(|t| t.span).collect();
+        assert_eq!(spans[1], Span { start: 6, end: 7 });
+        //extended mode ends with the group it was turned on in
+        let tokens: Vec<_> = Tokenizer::new("((?x) a ) b(?x: c ) d").map(|t| t.token).collect();
+        let chars: String = tokens
+            .iter()
+            .filter_map(|t| match t {
+                Token::Char(c) => Some(*c),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(chars, "a bc d");
+    }
+
+    #[test]
+    fn escapes() {
+        let tokens: Vec<_> = Tokenizer::new("\\b\\B\\*\\\\\\n").map(|t| t.token).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Assertion(Assertion::WordBoundary),
+                Token::Assertion(Assertion::NotWordBoundary),
+                Token::Char('*'),
+                Token::Char('\\'),
+                Token::Char('\n'),
+            ]
+        );
+        let mut tokens = Tokenizer::new("a\\q");
+        tokens.next();
+        assert_eq!(
+            tokens.next(),
+            Some(Spanned {
+                token: Token::Error(String::from("unknown escape '\\q'")),
+                span: Span { start: 1, end: 3 }
+            })
+        );
+        let mut tokens = Tokenizer::new("\\");
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::Error(String::from("trailing backslash"))));
+        assert_eq!(Token::Assertion(Assertion::WordBoundary).to_string(), "\\b");
+        let tokens: Vec<_> = Tokenizer::new("\\1\\9").map(|t| t.token).collect();
+        assert_eq!(tokens, vec![Token::Backref(1), Token::Backref(9)]);
+        assert_eq!(
+            Tokenizer::new("\\0").next().map(|t| t.token),
+            Some(Token::Error(String::from("unknown escape '\\0'")))
+        );
+        assert_eq!(
+            Tokenizer::new("[\\1]").next().map(|t| t.token),
+            Some(Token::Error(String::from("'\\1' cannot be used in a bracket expression")))
+        );
+    }
+
+    #[test]
+    fn classes() {
+        let tokens: Vec<_> = Tokenizer::new("\\d\\W\\s").map(|t| t.token).collect();
+        let class = |kind, negated| Token::Class(Class { kind, negated });
+        assert_eq!(
+            tokens,
+            vec![
+                class(ClassKind::Digit, false),
+                class(ClassKind::Word, true),
+                class(ClassKind::Space, false),
+            ]
+        );
+        assert_eq!(class(ClassKind::Space, true).to_string(), "\\S");
+    }
+
+    #[test]
+    fn properties() {
+        let property = |pattern| match Tokenizer::new(pattern).next().map(|t| t.token) {
+            Some(Token::Class(class)) => class.to_string(),
+            token => panic!("{:?}", token),
+        };
+        assert_eq!(property("\\p{Greek}"), "\\p{Greek}");
+        assert_eq!(property("\\pL"), "\\p{L}");
+        assert_eq!(property("\\P{decimal number}"), "\\P{Nd}");
+        assert_eq!(property("\\p{sc=Cyrl}"), "\\p{Cyrillic}");
+        let tokens: Vec<_> = Tokenizer::new("\\pLu").map(|t| t.token).collect();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[1], Token::Char('u'));
+        let bracket = Tokenizer::new("[\\p{Nd}_]").next().unwrap().token;
+        assert!(matches!(bracket, Token::Bracket(_)));
+        assert_eq!(bracket.to_string(), "[\\p{Nd}_]");
+        let error = |pattern| Tokenizer::new(pattern).next().unwrap().token;
+        assert_eq!(error("\\p{Klingon}"), Token::Error(String::from("unknown Unicode property 'Klingon'")));
+        assert_eq!(error("\\p{Greek"), Token::Error(String::from("unclosed '\\p{'")));
+        assert_eq!(error("\\p"), Token::Error(String::from("expected a property name after '\\p'")));
+    }
+
+    #[test]
+    fn brackets() {
+        let bracket = |pattern| match Tokenizer::new(pattern).next().map(|t| t.token) {
+            Some(Token::Bracket(bracket)) => bracket,
+            token => panic!("expected a bracket expression, found {:?}", token),
+        };
+        assert_eq!(
+            bracket("[a-z_]"),
+            Bracket {
+                negated: false,
+                items: vec![BracketItem::Range('a', 'z'), BracketItem::Range('_', '_')],
+            }
+        );
+        assert_eq!(
+            bracket("[^]a-]"),
+            Bracket {
+                negated: true,
+                items: vec![
+                    BracketItem::Range(']', ']'),
+                    BracketItem::Range('a', 'a'),
+                    BracketItem::Range('-', '-')
+                ],
+            }
+        );
+        assert_eq!(
+            bracket("[[:alpha:]\\d\\]]").items,
+            vec![
+                BracketItem::Posix(PosixClass::Alpha),
+                BracketItem::Class(Class {
+                    kind: ClassKind::Digit,
+                    negated: false
+                }),
+                BracketItem::Range(']', ']'),
+            ]
+        );
+        assert_eq!(bracket("[.*(]").items.len(), 3);
+        let mut tokens = Tokenizer::new("[[:space:]x]y");
+        assert_eq!(tokens.next().map(|t| t.span), Some(Span { start: 0, end: 12 }));
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::Char('y')));
+        assert_eq!(Token::Bracket(bracket("[^a-c[:digit:]\\s-]")).to_string(), "[^a-c[:digit:]\\s-]");
+    }
+
+    #[test]
+    fn bracket_errors() {
+        let error = |pattern| Tokenizer::new(pattern).next().unwrap();
+        assert_eq!(
+            error("[[:alfa:]]"),
+            Spanned {
+                token: Token::Error(String::from("unknown POSIX class 'alfa'")),
+                span: Span { start: 1, end: 9 }
+            }
+        );
+        assert_eq!(error("[z-a]").token, Token::Error(String::from("invalid range 'z-a'")));
+        assert_eq!(error("[z-a]").span, Span { start: 1, end: 4 });
+        assert_eq!(error("[a-\\d]").token, Token::Error(String::from("a class cannot end a range")));
+        assert_eq!(error("[abc").token, Token::Error(String::from("unclosed '['")));
+        assert_eq!(error("[]").token, Token::Error(String::from("unclosed '['")));
+        assert_eq!(
+            error("[:digit:]").token,
+            Token::Error(String::from("character class syntax is [[:digit:]], not [:digit:]"))
+        );
+        assert_eq!(
+            error("[\\b]").token,
+            Token::Error(String::from("'\\b' cannot be used in a bracket expression"))
+        );
+    }
+
+    #[test]
+    fn looks() {
+        let tokens: Vec<_> = Tokenizer::new("(?=a)(?<!b)").map(|t| t.token).collect();
+        let look = |behind, negated| Token::Look(Look { behind, negated });
+        assert_eq!(
+            tokens,
+            vec![
+                look(false, false),
+                Token::Char('a'),
+                Token::RParen,
+                look(true, true),
+                Token::Char('b'),
+                Token::RParen,
+            ]
+        );
+        assert_eq!(look(false, true).to_string(), "(?!");
+        assert_eq!(look(true, false).to_string(), "(?<=");
+        let spans: Vec<_> = Tokenizer::new("(?<=a)").map(|t| t.span).collect();
+        assert_eq!(spans[0], Span { start: 0, end: 4 });
+        assert_eq!(
+            Tokenizer::new("(?<a)").next().map(|t| t.token),
+            Some(Token::Error(String::from("unknown flag '<'")))
+        );
+    }
+
+    #[test]
+    fn named_groups() {
+        let tokens: Vec<_> = Tokenizer::new("(?P<user_1>a)").map(|t| t.token).collect();
+        assert_eq!(
+            tokens,
+            vec![Token::NamedGroup(String::from("user_1")), Token::Char('a'), Token::RParen]
+        );
+        let spans: Vec<_> = Tokenizer::new("(?P<id>a)").map(|t| t.span).collect();
+        assert_eq!(spans[0], Span { start: 0, end: 7 });
+        assert_eq!(Token::NamedGroup(String::from("id")).to_string(), "(?P<id>");
+        let error = |pattern| Tokenizer::new(pattern).next().map(|t| (t.token, t.span));
+        assert_eq!(
+            error("(?P<a-b>)"),
+            Some((
+                Token::Error(String::from("'-' cannot be used in a group name")),
+                Span { start: 5, end: 6 }
+            ))
+        );
+        assert_eq!(
+            error("(?P<1>)").map(|(token, _)| token),
+            Some(Token::Error(String::from("group names start with a letter or '_'")))
+        );
+        assert_eq!(
+            error("(?P<>)").map(|(token, _)| token),
+            Some(Token::Error(String::from("group names start with a letter or '_'")))
+        );
+        assert_eq!(
+            error("(?P<ab").map(|(token, _)| token),
+            Some(Token::Error(String::from("unclosed group name")))
+        );
+    }
+
+    #[test]
+    fn flag_errors() {
+        let mut tokens = Tokenizer::new("a(?iz)");
+        tokens.next();
+        assert_eq!(
+            tokens.next(),
+            Some(Spanned {
+                token: Token::Error(String::from("unknown flag 'z'")),
+                span: Span { start: 4, end: 5 }
+            })
+        );
+        let mut tokens = Tokenizer::new("(?i");
+        assert_eq!(
+            tokens.next(),
+            Some(Spanned {
+                token: Token::Error(String::from("unclosed flag group")),
+                span: Span { start: 0, end: 3 }
+            })
+        );
+        let mut tokens = Tokenizer::new("(?-)");
+        assert_eq!(
+            tokens.next(),
+            Some(Spanned {
+                token: Token::Error(String::from("expected a flag")),
+                span: Span { start: 3, end: 4 }
+            })
+        );
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Token::RParen.to_string(), ")");
+        assert_eq!(Token::KleenePlus.to_string(), "+");
+        assert_eq!(Token::Char('a').to_string(), "a");
+        assert_eq!(Token::Char('é').to_string(), "é");
+        assert_eq!(Token::Char('\t').to_string(), "\\t");
     }
 
     #[test]
     fn whitespace() {
         let mut tokens = Tokenizer::new("\n\t ");
-        assert_eq!(tokens.next(), Some(Token::Char('\n')));
-        assert_eq!(tokens.next(), Some(Token::Char('\t')));
-        assert_eq!(tokens.next(), Some(Token::Char(' ')));
-        assert_eq!(tokens.next(), None);
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::Char('\n')));
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::Char('\t')));
+        assert_eq!(tokens.next().map(|t| t.token), Some(Token::Char(' ')));
+        assert_eq!(tokens.next().map(|t| t.token), None);
     }
 }
 
@@ -127,10 +801,9 @@ mod iterator {
  * Helper methods for each token
  * type are defined below
  */
-
 impl<'str> Tokenizer<'str> {
     fn lex_paren(&mut self) -> Token {
-        let c = self.chars.next().unwrap();
+        let c = self.bump();
         match c {
             '(' => Token::LParen,
             ')' => Token::RParen,
@@ -139,7 +812,7 @@ impl<'str> Tokenizer<'str> {
     }
 
     fn lex_union(&mut self) -> Token {
-        let c = self.chars.next().unwrap();
+        let c = self.bump();
         match c {
             '|' => Token::UnionBar,
             _ => panic!("unknown register"),
@@ -147,15 +820,16 @@ impl<'str> Tokenizer<'str> {
     }
 
     fn lex_kleene(&mut self) -> Token {
-        let c = self.chars.next().unwrap();
+        let c = self.bump();
         match c {
+            '*' if self.lazy() => Token::LazyStar,
             '*' => Token::KleeneStar,
             _ => panic!("unknown register"),
         }
     }
 
     fn lex_anychar(&mut self) -> Token {
-        let c = self.chars.next().unwrap();
+        let c = self.bump();
         match c {
             '.' => Token::AnyChar,
             _ => panic!("unknown register"),
@@ -163,25 +837,341 @@ impl<'str> Tokenizer<'str> {
     }
 
     fn lex_char(&mut self) -> Token {
-        let c = self.chars.next().unwrap();
-        match c {
-            c => Token::Char(c),
-        }
+        Token::Char(self.bump())
     }
 
     fn lex_kleeneplus(&mut self) -> Token {
-        let c = self.chars.next().unwrap();
+        let c = self.bump();
         match c {
+            '+' if self.lazy() => Token::LazyPlus,
             '+' => Token::KleenePlus,
             _ => panic!("unknown register"),
         }
     }
+
+    fn lex_question(&mut self) -> Token {
+        let c = self.bump();
+        match c {
+            '?' if self.lazy() => Token::LazyQuestion,
+            '?' => Token::Question,
+            _ => panic!("unknown register"),
+        }
+    }
+
+    //takes the '?' that makes the quantifier just lexed lazy, if there is one
+    fn lazy(&mut self) -> bool {
+        let lazy = self.chars.peek() == Some(&'?');
+        if lazy {
+            self.bump();
+        }
+        lazy
+    }
+
+    //lexes a backslash and the char after it
+    fn lex_escape(&mut self) -> Token {
+        self.bump();
+        match self.chars.peek() {
+            Some('p') | Some('P') => self.lex_property(),
+            Some(_) => escape(self.bump()),
+            None => Token::Error(String::from("trailing backslash")),
+        }
+    }
+
+    //lexes the p or P of a Unicode property class and the name after it, which is either a
+    //single letter as in \pL or in braces as in \p{Greek}
+    fn lex_property(&mut self) -> Token {
+        let negated = self.bump() == 'P';
+        let name = match self.chars.peek() {
+            None => return Token::Error(String::from("expected a property name after '\\p'")),
+            Some('{') => {
+                self.bump();
+                let mut name = String::new();
+                loop {
+                    match self.chars.peek() {
+                        None => return Token::Error(String::from("unclosed '\\p{'")),
+                        Some('}') => {
+                            self.bump();
+                            break;
+                        }
+                        Some(_) => name.push(self.bump()),
+                    }
+                }
+                name
+            }
+            Some(_) => self.bump().to_string(),
+        };
+        match unicode_tables::property(&name) {
+            Some((name, ranges)) => Token::Class(Class {
+                kind: ClassKind::Property(Property { name, ranges }),
+                negated,
+            }),
+            None => Token::Error(format!("unknown Unicode property '{}'", name)),
+        }
+    }
+
+    //lexes a bracket expression like [a-z_] or [^[:space:]] as a single token
+    fn lex_bracket(&mut self) -> Token {
+        self.bump();
+        let negated = self.chars.peek() == Some(&'^');
+        if negated {
+            self.bump();
+        }
+        //[:alpha:] on its own is a bracket expression of the chars in ":alph", which is never
+        //what was meant
+        if !negated && self.chars.peek() == Some(&':') {
+            let text: String = self.chars.clone().take_while(|c| *c != ']').collect();
+            let name = text.strip_prefix(':').and_then(|text| text.strip_suffix(':'));
+            if name.and_then(PosixClass::from_name).is_some() {
+                return Token::Error(format!("character class syntax is [[{0}]], not [{0}]", text));
+            }
+        }
+        let mut items = vec![];
+        let mut first = true;
+        loop {
+            match self.chars.peek() {
+                None => return Token::Error(String::from("unclosed '['")),
+                Some(']') if !first => {
+                    self.bump();
+                    break;
+                }
+                Some(_) => first = false,
+            }
+            let start = self.pos;
+            let item = match self.bracket_item() {
+                Ok(item) => item,
+                Err(token) => return token,
+            };
+            let lo = match item {
+                BracketItem::Range(lo, _) => lo,
+                item => {
+                    items.push(item);
+                    continue;
+                }
+            };
+            //a - before the closing ] is just a -
+            if self.chars.peek() != Some(&'-') || matches!(self.second(), None | Some(']')) {
+                items.push(item);
+                continue;
+            }
+            self.bump();
+            match self.bracket_item() {
+                Ok(BracketItem::Range(hi, _)) if lo <= hi => items.push(BracketItem::Range(lo, hi)),
+                Ok(BracketItem::Range(hi, _)) => {
+                    self.start = start;
+                    return Token::Error(format!("invalid range '{}-{}'", lo, hi));
+                }
+                Ok(_) => {
+                    self.start = start;
+                    return Token::Error(String::from("a class cannot end a range"));
+                }
+                Err(token) => return token,
+            }
+        }
+        Token::Bracket(Bracket { negated, items })
+    }
+
+    //lexes one item of a bracket expression, which is a char, an escape or a POSIX class. Chars
+    //come back as ranges of one for lex_bracket to join into longer ones
+    fn bracket_item(&mut self) -> Result<BracketItem, Token> {
+        let start = self.pos;
+        match self.bump() {
+            '[' if self.chars.peek() == Some(&':') => {
+                self.bump();
+                let mut name = String::new();
+                loop {
+                    match self.chars.peek().copied() {
+                        None => return Err(Token::Error(String::from("unclosed '['"))),
+                        Some(':') if self.second() == Some(']') => break,
+                        Some(_) => name.push(self.bump()),
+                    }
+                }
+                self.bump();
+                self.bump();
+                PosixClass::from_name(&name).map(BracketItem::Posix).ok_or_else(|| {
+                    self.start = start;
+                    Token::Error(format!("unknown POSIX class '{}'", name))
+                })
+            }
+            '\\' => {
+                let token = match self.chars.peek() {
+                    None => return Err(Token::Error(String::from("trailing backslash"))),
+                    Some('p') | Some('P') => self.lex_property(),
+                    Some(_) => escape(self.bump()),
+                };
+                match token {
+                    Token::Char(c) => Ok(BracketItem::Range(c, c)),
+                    Token::Class(class) => Ok(BracketItem::Class(class)),
+                    token => {
+                        self.start = start;
+                        Err(match token {
+                            Token::Error(msg) => Token::Error(msg),
+                            token => Token::Error(format!("'{}' cannot be used in a bracket expression", token)),
+                        })
+                    }
+                }
+            }
+            c => Ok(BracketItem::Range(c, c)),
+        }
+    }
+
+    //lexes the opening of a lookaround group, which is (?= or (?! or the same with a '<' before
+    //the '=' or '!' to look behind, if that is what comes next
+    fn lex_look(&mut self) -> Option<Token> {
+        let mut chars = self.chars.clone().skip(2).peekable();
+        let behind = chars.next_if_eq(&'<').is_some();
+        let negated = match chars.next() {
+            Some('=') => false,
+            Some('!') => true,
+            _ => return None,
+        };
+        for _ in 0..3 + behind as usize {
+            self.bump();
+        }
+        Some(Token::Look(Look { behind, negated }))
+    }
+
+    //lexes the opening of a named group, (?P<name>, if that is what comes next. Names are made of
+    //ASCII letters, digits and '_', and do not start with a digit
+    fn lex_named(&mut self) -> Option<Token> {
+        let mut chars = self.chars.clone().skip(2);
+        if chars.next() != Some('P') || chars.next() != Some('<') {
+            return None;
+        }
+        for _ in 0..4 {
+            self.bump();
+        }
+        let mut name = String::new();
+        loop {
+            match self.chars.peek() {
+                None => return Some(Token::Error(String::from("unclosed group name"))),
+                Some('>') => {
+                    self.bump();
+                    break;
+                }
+                Some(&c) if c == '_' || c.is_ascii_alphanumeric() => {
+                    name.push(c);
+                    self.bump();
+                }
+                Some(&c) => {
+                    self.start = self.pos;
+                    self.bump();
+                    return Some(Token::Error(format!("'{}' cannot be used in a group name", c)));
+                }
+            }
+        }
+        if !name.starts_with(|c: char| c == '_' || c.is_ascii_alphabetic()) {
+            return Some(Token::Error(String::from("group names start with a letter or '_'")));
+        }
+        Some(Token::NamedGroup(name))
+    }
+
+    //lexes an inline flag group such as (?i) or (?-i), or the opening of a non-capturing group
+    //such as (?: or (?i:. A '-' turns off the flags after it
+    fn lex_flags(&mut self) -> Token {
+        self.bump();
+        self.bump();
+        let mut change = FlagChange::default();
+        let mut negated = false;
+        let mut empty = true;
+        let group = loop {
+            match self.chars.peek() {
+                None => return Token::Error(String::from("unclosed flag group")),
+                Some(')') => {
+                    self.bump();
+                    break false;
+                }
+                Some(':') => {
+                    self.bump();
+                    break true;
+                }
+                Some('-') if !negated => {
+                    negated = true;
+                    empty = true;
+                    self.bump();
+                }
+                Some(&flag @ ('i' | 's' | 'x')) => {
+                    let on = match flag {
+                        'i' => &mut change.case_insensitive,
+                        's' => &mut change.dot_all,
+                        _ => &mut change.extended,
+                    };
+                    *on = Some(!negated);
+                    empty = false;
+                    self.bump();
+                }
+                Some(&c) => {
+                    self.start = self.pos;
+                    self.bump();
+                    return Token::Error(format!("unknown flag '{}'", c));
+                }
+            }
+        };
+        //(?: needs no flags, but a '-' has to be followed by some
+        if empty && (negated || !group) {
+            self.start = self.pos - 1;
+            return Token::Error(String::from("expected a flag"));
+        }
+        if group {
+            Token::NonCapturing(change)
+        } else {
+            Token::Flags(change)
+        }
+    }
+
+    //in extended mode, skips the whitespace and comments ahead of the next token
+    fn skip_extended(&mut self) {
+        if !self.extended.last().copied().unwrap_or(false) {
+            return;
+        }
+        while let Some(&c) = self.chars.peek() {
+            if c == '#' {
+                while self.chars.peek().is_some_and(|c| *c != '\n') {
+                    self.bump();
+                }
+            } else if c.is_whitespace() {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+    }
+
+    //keeps track of the groups a token opens and closes, and of extended mode in each, since a
+    //flag only lasts until the end of the group it is set in
+    fn scope(&mut self, token: &Token) {
+        let extended = self.extended.last().copied().unwrap_or(false);
+        match token {
+            Token::LParen | Token::NamedGroup(_) | Token::Look(_) => self.extended.push(extended),
+            Token::NonCapturing(change) => self.extended.push(change.extended.unwrap_or(extended)),
+            Token::RParen if self.extended.len() > 1 => {
+                self.extended.pop();
+            }
+            Token::Flags(change) => {
+                if let Some(last) = self.extended.last_mut() {
+                    *last = change.extended.unwrap_or(*last);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    //looks at the character after the next one without consuming either
+    fn second(&self) -> Option<char> {
+        let mut chars = self.chars.clone();
+        chars.next();
+        chars.next()
+    }
+
+    //advances past the next character of input, keeping track of the column we are at
+    fn bump(&mut self) -> char {
+        self.pos += 1;
+        self.chars.next().unwrap()
+    }
 }
 
 /**
  * Tests for helper methods
  */
-
 #[cfg(test)]
 mod helper_method {
     use super::*;
@@ -227,4 +1217,23 @@ mod helper_method {
         assert_eq!(tokens.lex_kleeneplus(), Token::KleenePlus);
         assert_eq!(tokens.next(), None);
     }
+
+    #[test]
+    fn lex_question() {
+        let mut tokens = Tokenizer::new("?");
+        assert_eq!(tokens.lex_question(), Token::Question);
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn lazy() {
+        let tokens: Vec<_> = Tokenizer::new("*?+???*").map(|t| t.token).collect();
+        assert_eq!(
+            tokens,
+            vec![Token::LazyStar, Token::LazyPlus, Token::LazyQuestion, Token::KleeneStar]
+        );
+        let spans: Vec<_> = Tokenizer::new("a+?").map(|t| t.span).collect();
+        assert_eq!(spans[1], Span { start: 1, end: 3 });
+        assert_eq!(Token::LazyQuestion.to_string(), "??");
+    }
 }
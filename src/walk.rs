@@ -0,0 +1,334 @@
+mod glob;
+mod ignore;
+
+pub use self::glob::Glob;
+use self::ignore::Ignore;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/**
+ * Options that decide which files a Walker finds below a directory.
+ * - include, when not empty, keeps only files whose names match one of
+ *   its globs
+ * - exclude skips files whose names match one of its globs
+ * - hidden walks files and directories whose names start with a dot
+ * - ignore_files skips what .gitignore and .ignore files say to
+ * - follow_links follows symbolic links rather than skipping them
+ */
+#[derive(Debug, Clone)]
+pub struct WalkOptions {
+    pub include: Vec<Glob>,
+    pub exclude: Vec<Glob>,
+    pub hidden: bool,
+    pub ignore_files: bool,
+    pub follow_links: bool,
+}
+
+impl Default for WalkOptions {
+    fn default() -> WalkOptions {
+        WalkOptions {
+            include: vec![],
+            exclude: vec![],
+            hidden: false,
+            ignore_files: true,
+            follow_links: false,
+        }
+    }
+}
+
+/**
+ * What a Walker finds: a file to search, or a path that could not be
+ * walked along with why.
+ */
+#[derive(Debug)]
+pub enum Entry {
+    File(PathBuf),
+    Error(PathBuf, io::Error),
+}
+
+/**
+ * A Walker finds the files to search below a directory, in order of
+ * their names so that output is the same from one run to the next.
+ */
+pub struct Walker {
+    options: WalkOptions,
+}
+
+impl Walker {
+    pub fn new(options: WalkOptions) -> Walker {
+        Walker { options }
+    }
+
+    /**
+     * Find the files to search at root. A root that is not a directory
+     * is searched as it is, without being checked against the options,
+     * and a root that is a symbolic link is always followed.
+     */
+    pub fn walk(&self, root: &Path) -> Vec<Entry> {
+        let mut found = vec![];
+        match fs::metadata(root) {
+            Ok(metadata) if metadata.is_dir() => {
+                self.dir(root, &mut vec![], &mut vec![], &mut found)
+            }
+            _ => found.push(Entry::File(root.to_path_buf())),
+        }
+        found
+    }
+
+    /**
+     * Walk the directory dir. ignores holds the ignore files of the
+     * directories above it along with the directories they are in, and
+     * ancestors the real paths of those directories, which is how a
+     * symbolic link back up the tree is caught before it loops forever.
+     */
+    fn dir(
+        &self,
+        dir: &Path,
+        ignores: &mut Vec<(PathBuf, Ignore)>,
+        ancestors: &mut Vec<PathBuf>,
+        found: &mut Vec<Entry>,
+    ) {
+        if self.options.follow_links {
+            match fs::canonicalize(dir) {
+                Ok(real) if ancestors.contains(&real) => {
+                    let e = io::Error::other("recursive directory loop");
+                    found.push(Entry::Error(dir.to_path_buf(), e));
+                    return;
+                }
+                Ok(real) => ancestors.push(real),
+                Err(e) => {
+                    found.push(Entry::Error(dir.to_path_buf(), e));
+                    return;
+                }
+            }
+        }
+        let ignore = if self.options.ignore_files {
+            Ignore::read(dir).unwrap_or_else(|e| {
+                found.push(Entry::Error(dir.to_path_buf(), e));
+                Ignore::default()
+            })
+        } else {
+            Ignore::default()
+        };
+        ignores.push((dir.to_path_buf(), ignore));
+        match read_dir(dir) {
+            Ok(entries) => {
+                for entry in entries {
+                    self.entry(&entry, ignores, ancestors, found);
+                }
+            }
+            Err(e) => found.push(Entry::Error(dir.to_path_buf(), e)),
+        }
+        ignores.pop();
+        if self.options.follow_links {
+            ancestors.pop();
+        }
+    }
+
+    fn entry(
+        &self,
+        entry: &fs::DirEntry,
+        ignores: &mut Vec<(PathBuf, Ignore)>,
+        ancestors: &mut Vec<PathBuf>,
+        found: &mut Vec<Entry>,
+    ) {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if !self.options.hidden && name.starts_with('.') {
+            return;
+        }
+        let file_type = if self.options.follow_links {
+            fs::metadata(&path).map(|metadata| metadata.file_type())
+        } else {
+            entry.file_type()
+        };
+        let file_type = match file_type {
+            Ok(file_type) => file_type,
+            Err(e) => {
+                found.push(Entry::Error(path, e));
+                return;
+            }
+        };
+        if ignored(&path, file_type.is_dir(), ignores) {
+            return;
+        }
+        if file_type.is_dir() {
+            self.dir(&path, ignores, ancestors, found);
+        } else if file_type.is_file() && self.selected(&name) {
+            found.push(Entry::File(path));
+        }
+    }
+
+    /**
+     * Whether a file with the given name passes --include and --exclude.
+     */
+    fn selected(&self, name: &str) -> bool {
+        let include = &self.options.include;
+        (include.is_empty() || include.iter().any(|glob| glob.is_match(name)))
+            && !self.options.exclude.iter().any(|glob| glob.is_match(name))
+    }
+}
+
+/**
+ * The entries of a directory sorted by name.
+ */
+fn read_dir(dir: &Path) -> io::Result<Vec<fs::DirEntry>> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    Ok(entries)
+}
+
+/**
+ * Whether the ignore files say to skip path. The ignore file nearest to
+ * path with a rule about it decides.
+ */
+fn ignored(path: &Path, is_dir: bool, ignores: &[(PathBuf, Ignore)]) -> bool {
+    for (dir, ignore) in ignores.iter().rev() {
+        let relative = match path.strip_prefix(dir) {
+            Ok(relative) => relative,
+            Err(_) => continue,
+        };
+        let relative: Vec<_> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect();
+        if let Some(ignored) = ignore.matched(&relative.join("/"), is_dir) {
+            return ignored;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * A directory under the system's temporary directory that is removed
+     * when the test is done with it.
+     */
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir = std::env::temp_dir().join(format!("thegrep-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        fn file(&self, path: &str, contents: &str) {
+            let path = self.0.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    //the files a walk finds, relative to the root
+    fn walk(dir: &TempDir, options: WalkOptions) -> Vec<String> {
+        Walker::new(options)
+            .walk(&dir.0)
+            .into_iter()
+            .map(|entry| match entry {
+                Entry::File(path) => path.strip_prefix(&dir.0).unwrap().display().to_string(),
+                Entry::Error(path, e) => panic!("{}: {}", path.display(), e),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_files_in_order() {
+        let dir = TempDir::new("order");
+        dir.file("b.txt", "");
+        dir.file("a/z.txt", "");
+        dir.file("a/y/x.txt", "");
+        assert_eq!(walk(&dir, WalkOptions::default()), vec!["a/y/x.txt", "a/z.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        let dir = TempDir::new("globs");
+        dir.file("main.rs", "");
+        dir.file("notes.md", "");
+        dir.file("src/lib.rs", "");
+        dir.file("src/lib_test.rs", "");
+        let options = WalkOptions {
+            include: vec![Glob::new("*.rs")],
+            exclude: vec![Glob::new("*_test.rs")],
+            ..WalkOptions::default()
+        };
+        assert_eq!(walk(&dir, options), vec!["main.rs", "src/lib.rs"]);
+    }
+
+    #[test]
+    fn hidden() {
+        let dir = TempDir::new("hidden");
+        dir.file(".env", "");
+        dir.file(".git/config", "");
+        dir.file("visible", "");
+        assert_eq!(walk(&dir, WalkOptions::default()), vec!["visible"]);
+        let options = WalkOptions {
+            hidden: true,
+            ..WalkOptions::default()
+        };
+        assert_eq!(walk(&dir, options), vec![".env", ".git/config", "visible"]);
+    }
+
+    #[test]
+    fn ignore_files() {
+        let dir = TempDir::new("ignore");
+        dir.file(".gitignore", "target/\n*.log\n");
+        dir.file("target/debug/out", "");
+        dir.file("build.log", "");
+        dir.file("src/main.rs", "");
+        dir.file("src/.ignore", "!keep.log\n/generated.rs\n");
+        dir.file("src/keep.log", "");
+        dir.file("src/generated.rs", "");
+        dir.file("src/nested/generated.rs", "");
+        assert_eq!(
+            walk(&dir, WalkOptions::default()),
+            vec!["src/keep.log", "src/main.rs", "src/nested/generated.rs"]
+        );
+        let options = WalkOptions {
+            ignore_files: false,
+            ..WalkOptions::default()
+        };
+        assert_eq!(walk(&dir, options).len(), 6);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() {
+        use std::os::unix::fs::symlink;
+        let dir = TempDir::new("symlinks");
+        dir.file("a/file", "");
+        symlink(dir.0.join("a"), dir.0.join("a/loop")).unwrap();
+        symlink(dir.0.join("a/file"), dir.0.join("link")).unwrap();
+        assert_eq!(walk(&dir, WalkOptions::default()), vec!["a/file"]);
+        let options = WalkOptions {
+            follow_links: true,
+            ..WalkOptions::default()
+        };
+        let entries = Walker::new(options).walk(&dir.0);
+        assert_eq!(entries.len(), 3);
+        match &entries[1] {
+            Entry::Error(path, e) => {
+                assert_eq!(path, &dir.0.join("a/loop"));
+                assert_eq!(e.to_string(), "recursive directory loop");
+            }
+            entry => panic!("expected a loop, found {:?}", entry),
+        }
+        match &entries[2] {
+            Entry::File(path) => assert_eq!(path, &dir.0.join("link")),
+            entry => panic!("expected the link, found {:?}", entry),
+        }
+    }
+}
@@ -0,0 +1,153 @@
+/**
+ * A Glob is a shell-style wildcard pattern for file names and paths.
+ * - * matches any run of characters other than /
+ * - ** matches any run of characters at all, and ** followed by a /
+ *   matches any number of whole directories, including none
+ * - ? matches any one character other than /
+ * - [abc], [a-z] and [!a-z] or [^a-z] match one character from a class
+ * - \ makes the character after it literal
+ *
+ * A [ without a closing ] is just a [.
+ */
+#[derive(Debug, Clone)]
+pub struct Glob {
+    pattern: Vec<char>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Glob {
+        Glob {
+            pattern: pattern.chars().collect(),
+        }
+    }
+
+    /**
+     * Whether the whole of text is matched by the Glob.
+     */
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        matches(&self.pattern, &text)
+    }
+}
+
+fn matches(p: &[char], t: &[char]) -> bool {
+    match p.first() {
+        None => t.is_empty(),
+        Some('*') if p.get(1) == Some(&'*') => {
+            let rest = &p[2..];
+            if rest.first() == Some(&'/') {
+                //**/ can stand for no directories, or any number of them
+                let rest = &rest[1..];
+                matches(rest, t) || (0..t.len()).any(|i| t[i] == '/' && matches(rest, &t[i + 1..]))
+            } else {
+                (0..=t.len()).any(|i| matches(rest, &t[i..]))
+            }
+        }
+        Some('*') => {
+            for i in 0..=t.len() {
+                if matches(&p[1..], &t[i..]) {
+                    return true;
+                }
+                if i < t.len() && t[i] == '/' {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => t.first().is_some_and(|c| *c != '/') && matches(&p[1..], &t[1..]),
+        Some('[') => match class(&p[1..]) {
+            Some((set, len)) => {
+                t.first().is_some_and(|c| *c != '/' && set.contains(*c))
+                    && matches(&p[1 + len..], &t[1..])
+            }
+            None => t.first() == Some(&'[') && matches(&p[1..], &t[1..]),
+        },
+        Some('\\') if p.len() > 1 => t.first() == Some(&p[1]) && matches(&p[2..], &t[1..]),
+        Some(c) => t.first() == Some(c) && matches(&p[1..], &t[1..]),
+    }
+}
+
+/**
+ * The class of characters in a bracket expression.
+ */
+struct Class {
+    negated: bool,
+    ranges: Vec<(char, char)>,
+}
+
+impl Class {
+    fn contains(&self, c: char) -> bool {
+        self.ranges.iter().any(|(lo, hi)| *lo <= c && c <= *hi) != self.negated
+    }
+}
+
+/**
+ * Parse the class at the start of p, which follows a [. Returns the class
+ * and how many chars it took up, including the closing ], or None if the
+ * class is never closed. A ] right at the start of the class is literal.
+ */
+fn class(p: &[char]) -> Option<(Class, usize)> {
+    let mut i = 0;
+    let negated = matches!(p.first(), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut ranges = vec![];
+    let first = i;
+    loop {
+        let lo = *p.get(i)?;
+        if lo == ']' && i > first {
+            return Some((Class { negated, ranges }, i + 1));
+        }
+        if p.get(i + 1) == Some(&'-') && p.get(i + 2).is_some_and(|c| *c != ']') {
+            ranges.push((lo, p[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((lo, lo));
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, text: &str) -> bool {
+        Glob::new(pattern).is_match(text)
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(is_match("*.rs", "main.rs"));
+        assert!(is_match("*.rs", ".rs"));
+        assert!(!is_match("*.rs", "main.rs.bak"));
+        assert!(!is_match("*.rs", "src/main.rs"));
+        assert!(is_match("ma?n.rs", "main.rs"));
+        assert!(!is_match("ma?n.rs", "man.rs"));
+        assert!(is_match("\\*", "*"));
+        assert!(!is_match("\\*", "a"));
+    }
+
+    #[test]
+    fn double_star() {
+        assert!(is_match("**/target", "target"));
+        assert!(is_match("**/target", "a/b/target"));
+        assert!(is_match("src/**/*.rs", "src/main.rs"));
+        assert!(is_match("src/**/*.rs", "src/nfa/unicode_tables/case_folding.rs"));
+        assert!(!is_match("src/**/*.rs", "tests/main.rs"));
+        assert!(is_match("docs/**", "docs/a/b.md"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(is_match("[abc].txt", "b.txt"));
+        assert!(!is_match("[abc].txt", "d.txt"));
+        assert!(is_match("file[0-9]", "file7"));
+        assert!(is_match("file[!0-9]", "filex"));
+        assert!(!is_match("file[^0-9]", "file7"));
+        assert!(is_match("[]]", "]"));
+        assert!(is_match("[a-]", "-"));
+        assert!(is_match("[ab", "[ab"));
+    }
+}
@@ -0,0 +1,142 @@
+use super::glob::Glob;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/**
+ * The names of the ignore files read in each directory walked, in order
+ * of increasing precedence.
+ */
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+/**
+ * The rules of the ignore files in a single directory, following the
+ * format of .gitignore.
+ * - blank lines and lines starting with # are skipped
+ * - a leading ! re-includes what an earlier rule ignored
+ * - a trailing / only matches directories
+ * - a pattern with a / at its start or middle is matched against the
+ *   path relative to the directory, and one without against the name
+ *   of the file or directory at any depth below it
+ *
+ * \# and \! match a literal # or ! at the start of a pattern.
+ */
+#[derive(Debug, Default)]
+pub struct Ignore {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    glob: Glob,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl Ignore {
+    /**
+     * Read the ignore files in dir. Ignore files that do not exist are
+     * treated as empty.
+     */
+    pub fn read(dir: &Path) -> io::Result<Ignore> {
+        let mut ignore = Ignore::default();
+        for name in IGNORE_FILES {
+            match fs::read(dir.join(name)) {
+                Ok(bytes) => ignore.add(&String::from_utf8_lossy(&bytes)),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(ignore)
+    }
+
+    /**
+     * Add the rules in the text of an ignore file, taking precedence over
+     * those already added.
+     */
+    pub fn add(&mut self, text: &str) {
+        for line in text.lines() {
+            let mut line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            //a leading ! negates, and a leading \ escapes a ! or # meant literally
+            let negated = line.starts_with('!');
+            if negated || line.starts_with("\\!") || line.starts_with("\\#") {
+                line = &line[1..];
+            }
+            let dir_only = line.ends_with('/');
+            if dir_only {
+                line = &line[..line.len() - 1];
+            }
+            let anchored = line.contains('/');
+            let line = line.strip_prefix('/').unwrap_or(line);
+            if line.is_empty() {
+                continue;
+            }
+            self.rules.push(Rule {
+                glob: Glob::new(line),
+                negated,
+                dir_only,
+                anchored,
+            });
+        }
+    }
+
+    /**
+     * Whether the rules decide path, given relative to the directory with
+     * '/' separators, is ignored. None means no rule matches it, leaving
+     * the decision to the ignore files of directories further up.
+     */
+    pub fn matched(&self, path: &str, is_dir: bool) -> Option<bool> {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        //later rules win, so the last one to match is the one that counts
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                (is_dir || !rule.dir_only)
+                    && rule.glob.is_match(if rule.anchored { path } else { name })
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignore(text: &str) -> Ignore {
+        let mut ignore = Ignore::default();
+        ignore.add(text);
+        ignore
+    }
+
+    #[test]
+    fn names() {
+        let ignore = ignore("# build output\n\n*.o\ntarget/\n");
+        assert_eq!(ignore.matched("main.o", false), Some(true));
+        assert_eq!(ignore.matched("src/main.o", false), Some(true));
+        assert_eq!(ignore.matched("main.c", false), None);
+        assert_eq!(ignore.matched("a/target", true), Some(true));
+        assert_eq!(ignore.matched("target", false), None);
+    }
+
+    #[test]
+    fn anchored() {
+        let ignore = ignore("/todo.txt\ndoc/*.pdf\n");
+        assert_eq!(ignore.matched("todo.txt", false), Some(true));
+        assert_eq!(ignore.matched("a/todo.txt", false), None);
+        assert_eq!(ignore.matched("doc/a.pdf", false), Some(true));
+        assert_eq!(ignore.matched("a/doc/a.pdf", false), None);
+    }
+
+    #[test]
+    fn negated() {
+        let ignore = ignore("*.log\n!keep.log\n\\!bang\n");
+        assert_eq!(ignore.matched("debug.log", false), Some(true));
+        assert_eq!(ignore.matched("keep.log", false), Some(false));
+        assert_eq!(ignore.matched("!bang", false), Some(true));
+    }
+}